@@ -0,0 +1,110 @@
+use embedded_hal_async::spi::SpiDevice;
+use crate::config::{BitRateConfig, BitTiming, Clock};
+use crate::interrupt::InterruptPins;
+use crate::registers::*;
+use crate::{Error, MCP25xxFD};
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AutoBaudConfig {
+    /// How many times `CiBDIAG1` is read while listening with each candidate
+    ///
+    /// Each read is one short SPI transfer, so the listening time depends on the SPI clock. It
+    /// should cover several frames at the expected bus load.
+    pub polls_per_candidate: u32,
+    /// Error-free frames needed before a candidate is accepted
+    pub min_frames: u32,
+    /// Form, stuff and CRC errors tolerated for a candidate to be accepted, e.g. from frames
+    /// that were already in flight when listening started
+    pub max_errors: u32,
+}
+impl Default for AutoBaudConfig {
+    fn default() -> Self {
+        Self {
+            polls_per_candidate: 10_000,
+            min_frames: 4,
+            max_errors: 1,
+        }
+    }
+}
+
+/// The candidate chosen by [`MCP25xxFD::detect_bit_rate`]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AutoBaudResult {
+    pub bit_timing: BitTiming,
+    /// Register values of `bit_timing` for the system clock
+    pub config: BitRateConfig,
+    /// Error-free frames seen while listening
+    pub frames: u32,
+    /// Form, stuff and CRC errors seen while listening
+    pub errors: u32,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Find the bit timing used on the bus among `candidates`
+    ///
+    /// Each candidate valid for `clock` is tried in Listen Only mode, so the controller never
+    /// drives the bus, and judged by the error-free frames counted in `CiBDIAG1` against form,
+    /// stuff and CRC errors in either phase. Listening stops early once a candidate has enough
+    /// frames without errors. The candidate with the most frames that stays within
+    /// [`AutoBaudConfig::max_errors`] is returned.
+    ///
+    /// The controller is left in Configuration mode with the bit timing of the last candidate
+    /// tried; apply the result with [`BitTiming::Raw`] and
+    /// [`reset_and_apply_config`](Self::reset_and_apply_config).
+    pub async fn detect_bit_rate(
+        &mut self,
+        clock: &Clock,
+        candidates: impl IntoIterator<Item = BitTiming>,
+        config: &AutoBaudConfig,
+    ) -> Result<Option<AutoBaudResult>, Error<SPI>> {
+        let mut best: Option<AutoBaudResult> = None;
+
+        for bit_timing in candidates {
+            let Some(bit_rate_config) = bit_timing.get_config(clock).filter(BitRateConfig::is_valid) else {
+                continue;
+            };
+            self.enter_configuration_mode().await?;
+            self.write_bit_rate_config(&bit_rate_config).await?;
+            self.set_mode(OperationMode::ListenOnly).await?;
+            self.wait_for_mode(OperationMode::ListenOnly).await?;
+
+            let (frames, errors) = self.listen_for_frames(config).await?;
+            let accepted = frames >= config.min_frames && errors <= config.max_errors;
+            if accepted && best.as_ref().is_none_or(|best| frames > best.frames) {
+                best = Some(AutoBaudResult { bit_timing, config: bit_rate_config, frames, errors });
+            }
+            if accepted && errors == 0 {
+                break;
+            }
+        }
+
+        self.enter_configuration_mode().await?;
+        Ok(best)
+    }
+
+    /// Count error-free frames and errors until the poll budget runs out, or until enough frames
+    /// were seen without any error
+    async fn listen_for_frames(&mut self, config: &AutoBaudConfig) -> Result<(u32, u32), Error<SPI>> {
+        self.clear_bus_error_flags().await?;
+        let start: BusDiagnostic1 = self.read_register().await?;
+        let mut frames = 0;
+        let mut errors = 0;
+
+        for _ in 0..config.polls_per_candidate {
+            let diagnostic: BusDiagnostic1 = self.read_register().await?;
+            frames = diagnostic.efmsgcnt().wrapping_sub(start.efmsgcnt()) as u32;
+            let error = diagnostic.nformerr() || diagnostic.nstuferr() || diagnostic.ncrcerr()
+                || diagnostic.dformerr() || diagnostic.dstuferr() || diagnostic.dcrcerr();
+            if error {
+                errors += 1;
+                self.clear_bus_error_flags().await?;
+            }
+            else if errors == 0 && frames >= config.min_frames {
+                break;
+            }
+        }
+        Ok((frames, errors))
+    }
+}
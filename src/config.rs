@@ -1,5 +1,5 @@
 use embedded_can::{Id, StandardId};
-use crate::registers::{PayloadSize, RetransmissionAttempts};
+use crate::registers::{ClockDivisor, ClockOutputDivisor, DataBitTimeConfig, DelayCompensationMode, NominalBitTimeConfig, PayloadSize, RetransmissionAttempts, TransmitterDelayCompensation};
 
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -8,8 +8,27 @@ pub struct Config {
     pub txq_enabled: bool,
     pub tx_event_fifo_enabled: bool,
     pub iso_crc_enabled: bool,
-    pub restrict_retx_attempts: bool,
-    pub bit_rate: BitRate,
+    /// Retransmission policy of the TXQ
+    pub txq_retransmission: RetransmissionPolicy,
+    /// Delay between frames sent by this node
+    pub bandwidth_sharing: BandwidthSharing,
+    pub bit_timing: BitTiming,
+    /// Overrides the transmitter delay compensation chosen by `bit_timing`
+    pub delay_compensation: Option<DelayCompensationConfig>,
+    /// Enable edge filtering during bus integration
+    pub edge_filter_enabled: bool,
+    /// Transceiver standby control through the GPIO0/XSTBY pin
+    pub transceiver_standby: TransceiverStandby,
+    /// CLKO/SOF, TXCAN and interrupt pin configuration
+    pub pins: PinConfig,
+    /// Use GPIO0 as INT0, a dedicated transmit interrupt output
+    pub tx_interrupt_pin: bool,
+    /// Use GPIO1 as INT1, a dedicated receive interrupt output
+    pub rx_interrupt_pin: bool,
+    /// Interrupt sources enabled in `CiINT`
+    pub interrupts: InterruptConfig,
+    /// SPI CRC and command format error detection
+    pub spi_crc: SPICRCConfig,
     pub clock: Clock,
 }
 
@@ -20,20 +39,299 @@ impl Default for Config {
             txq_enabled: false,
             tx_event_fifo_enabled: false,
             iso_crc_enabled: true,
-            restrict_retx_attempts: false,
-            bit_rate: BitRate::default(),
+            txq_retransmission: RetransmissionPolicy::Unlimited,
+            bandwidth_sharing: BandwidthSharing::NoDelay,
+            bit_timing: BitTiming::default(),
+            delay_compensation: None,
+            edge_filter_enabled: false,
+            transceiver_standby: TransceiverStandby::Disabled,
+            pins: PinConfig::default(),
+            tx_interrupt_pin: false,
+            rx_interrupt_pin: false,
+            interrupts: InterruptConfig::default(),
+            spi_crc: SPICRCConfig::default(),
             clock: Clock::Clock40MHz,
         }
     }
 }
 
+impl Config {
+    /// The bit timing that will be written to the controller, including any delay compensation
+    /// override. Returns `None` if the combination is invalid for the configured clock.
+    pub fn bit_rate_config(&self) -> Option<BitRateConfig> {
+        let mut config = self.bit_timing.get_config(&self.clock)?;
+        if let Some(delay_compensation) = &self.delay_compensation {
+            config.tdc_mode = delay_compensation.mode;
+            config.tdc_offset = delay_compensation.offset;
+            config.tdc_value = delay_compensation.value;
+        }
+        config.is_valid().then_some(config)
+    }
+}
+
+/// Function of the CLKO/SOF pin
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockOutput {
+    /// Output the system clock through the given divisor
+    Clock(ClockOutputDivisor),
+    /// Pulse at the start of every frame on the bus
+    StartOfFrame,
+}
+
+/// Electrical configuration of the controller's output pins
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PinConfig {
+    pub clock_output: ClockOutput,
+    /// Drive TXCAN as open drain instead of push-pull
+    pub txcan_open_drain: bool,
+    /// Drive INT, INT0 and INT1 as open drain instead of push-pull, e.g. to wire-OR them
+    pub interrupt_open_drain: bool,
+}
+impl Default for PinConfig {
+    /// Matches the controller's reset state
+    fn default() -> Self {
+        Self {
+            clock_output: ClockOutput::Clock(ClockOutputDivisor::DivideBy10),
+            txcan_open_drain: false,
+            interrupt_open_drain: false,
+        }
+    }
+}
+
+/// Control of the transceiver standby input, wired to the GPIO0/XSTBY pin
+///
+/// On the MCP251863 the integrated transceiver's STBY input is connected to this pin internally.
+/// Enabling either mode reserves GPIO0, so it can't be used as a GPIO or as INT0.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransceiverStandby {
+    /// GPIO0 is left alone
+    Disabled,
+    /// The controller drives XSTBY high while in Sleep mode and low otherwise
+    Automatic,
+    /// GPIO0 is an output driven by the driver: high while the controller is put in Sleep mode
+    /// and on request through [`MCP25xxFD::set_transceiver_standby`](crate::MCP25xxFD::set_transceiver_standby)
+    Manual,
+}
+
+/// Delay the controller inserts between two of its own transmissions, in arbitration bit times
+///
+/// Leaves room on the bus for other nodes while this one has a lot to send.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BandwidthSharing {
+    #[default]
+    NoDelay,
+    Delay2,
+    Delay4,
+    Delay8,
+    Delay16,
+    Delay32,
+    Delay64,
+    Delay128,
+    Delay256,
+    Delay512,
+    Delay1024,
+    Delay2048,
+    Delay4096,
+}
+impl BandwidthSharing {
+    /// Value of `CiCON.TXBWS`
+    pub fn bits(&self) -> u8 {
+        *self as u8
+    }
+    pub fn from_bits(bits: u8) -> Self {
+        match bits {
+            0 => BandwidthSharing::NoDelay,
+            1 => BandwidthSharing::Delay2,
+            2 => BandwidthSharing::Delay4,
+            3 => BandwidthSharing::Delay8,
+            4 => BandwidthSharing::Delay16,
+            5 => BandwidthSharing::Delay32,
+            6 => BandwidthSharing::Delay64,
+            7 => BandwidthSharing::Delay128,
+            8 => BandwidthSharing::Delay256,
+            9 => BandwidthSharing::Delay512,
+            10 => BandwidthSharing::Delay1024,
+            11 => BandwidthSharing::Delay2048,
+            _ => BandwidthSharing::Delay4096,
+        }
+    }
+    /// Delay in arbitration bit times
+    pub fn bit_times(&self) -> u16 {
+        match self {
+            BandwidthSharing::NoDelay => 0,
+            delay => 1 << delay.bits(),
+        }
+    }
+}
+
+/// Transmitter delay compensation (TDC) settings
+///
+/// During the data phase the controller samples its own transmitted bits at a secondary sample
+/// point (SSP), located `value + offset` system clocks after the start of each bit. In automatic
+/// mode the controller measures the transceiver loop delay itself and `value` is ignored.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DelayCompensationConfig {
+    pub mode: DelayCompensationMode,
+    /// Offset in system clocks (-64 to 63), less than one data bit time
+    pub offset: i8,
+    /// Transceiver loop delay in system clocks (0 to 63), only used in manual mode
+    pub value: u8,
+}
+impl DelayCompensationConfig {
+    pub fn disabled() -> Self {
+        Self { mode: DelayCompensationMode::Disabled, offset: 0, value: 0 }
+    }
+    pub fn manual(value: u8, offset: i8) -> Self {
+        Self { mode: DelayCompensationMode::Manual, offset, value }
+    }
+    pub fn auto(offset: i8) -> Self {
+        Self { mode: DelayCompensationMode::Auto1, offset, value: 0 }
+    }
+}
+
+/// Global interrupt enables
+///
+/// FIFO interrupts are only raised for the conditions enabled in each FIFO's
+/// [`FIFOConfig::interrupts`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterruptConfig {
+    /// Receive FIFO interrupts
+    pub receive: bool,
+    /// Transmit FIFO interrupts
+    pub transmit: bool,
+    /// CAN bus errors
+    pub bus_error: bool,
+    /// Transmit Event FIFO interrupts
+    pub transmit_event_fifo: bool,
+    /// ECC errors in message RAM
+    pub ecc: bool,
+    /// SPI CRC errors
+    pub spi_crc: bool,
+    /// Transmit attempts exhausted
+    pub transmit_attempt: bool,
+    /// Receive FIFO overflow
+    pub receive_overflow: bool,
+    /// System errors (address errors, MAB underflow/overflow)
+    pub system_error: bool,
+    /// Bus wake-up
+    pub wake_up: bool,
+    /// Invalid message received
+    pub invalid_message: bool,
+    /// Time Base Counter overflow
+    pub time_base_counter: bool,
+    /// Operation mode change
+    pub mode_change: bool,
+}
+impl Default for InterruptConfig {
+    fn default() -> Self {
+        Self {
+            receive: true,
+            transmit: false,
+            bus_error: true,
+            transmit_event_fifo: false,
+            ecc: false,
+            spi_crc: false,
+            transmit_attempt: false,
+            receive_overflow: false,
+            system_error: false,
+            wake_up: false,
+            invalid_message: false,
+            time_base_counter: false,
+            mode_change: false,
+        }
+    }
+}
+
+/// Errors detected in SPI instructions with a CRC
+///
+/// Either error sets the SPI CRC interrupt flag when it is enabled here, which in turn raises
+/// the interrupt if [`InterruptConfig::spi_crc`] is set.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SPICRCConfig {
+    /// CRC mismatch in a received instruction
+    pub crc_error: bool,
+    /// Wrong number of bytes in a received instruction
+    pub format_error: bool,
+    /// How many times a [`write_register_safe`](crate::MCP25xxFD::write_register_safe) rejected
+    /// by the controller is repeated before giving up
+    pub write_retries: u8,
+}
+impl Default for SPICRCConfig {
+    fn default() -> Self {
+        Self {
+            crc_error: true,
+            format_error: true,
+            write_retries: 3,
+        }
+    }
+}
+
+/// FIFO conditions raising the receive or transmit interrupt
+///
+/// [`MCP25xxFD::receive`](crate::MCP25xxFD::receive) only looks at FIFOs with a pending
+/// interrupt, so an RX FIFO with only `half` enabled is read once it is half full, and then
+/// until it is empty.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FIFOInterrupts {
+    /// TX FIFO not full / RX FIFO not empty
+    pub not_full_not_empty: bool,
+    /// TX FIFO half empty / RX FIFO half full
+    pub half: bool,
+    /// TX FIFO empty / RX FIFO full
+    pub empty_full: bool,
+    /// RX FIFO overflow
+    pub overflow: bool,
+    /// TX FIFO transmit attempts exhausted
+    pub attempts_exhausted: bool,
+}
+
+/// How often a TX FIFO or the TXQ retries a frame that failed to send
+///
+/// A frame is retried after losing arbitration or after an error during transmission. The
+/// controller only honours the per-FIFO `TXAT` setting when `CiCON.RTXAT` restricts attempts
+/// globally, so the driver always sets `RTXAT` and expresses unlimited retries through `TXAT`.
+/// `RetransmissionAttempts::Unlimited1` and `Unlimited2` behave the same.
+///
+/// Once the attempts run out the FIFO stops sending and flags `TXATIF`, see
+/// [`MCP25xxFD::take_attempts_exhausted`](crate::MCP25xxFD::take_attempts_exhausted) and
+/// [`TransmitOutcome::AttemptsExhausted`](crate::transmit::TransmitOutcome::AttemptsExhausted).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RetransmissionPolicy {
+    /// Each frame is sent once, as needed for time-triggered communication
+    OneShot,
+    /// Up to three retransmissions after the first attempt
+    ThreeRetransmissions,
+    /// Retry until the frame is sent or aborted
+    #[default]
+    Unlimited,
+}
+impl RetransmissionPolicy {
+    pub(crate) fn attempts(&self) -> RetransmissionAttempts {
+        match self {
+            RetransmissionPolicy::OneShot => RetransmissionAttempts::Disable,
+            RetransmissionPolicy::ThreeRetransmissions => RetransmissionAttempts::Three,
+            RetransmissionPolicy::Unlimited => RetransmissionAttempts::Unlimited1,
+        }
+    }
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FIFOConfig<const M: u8> {
     pub size: u8,
     pub payload_size: PayloadSize,
     pub transmit: bool,
-    pub tx_attempts: RetransmissionAttempts,
+    pub retransmission: RetransmissionPolicy,
     pub priority: u8,
+    pub interrupts: FIFOInterrupts,
 }
 impl<const M: u8> FIFOConfig<M> {
     pub fn rx_with_size(size: u8, payload_size: PayloadSize) -> Self {
@@ -41,13 +339,15 @@ impl<const M: u8> FIFOConfig<M> {
             size,
             payload_size,
             transmit: false,
-            tx_attempts: RetransmissionAttempts::Unlimited1,
+            retransmission: RetransmissionPolicy::Unlimited,
             priority: 0,
+            interrupts: FIFOInterrupts { not_full_not_empty: true, ..Default::default() },
         }
     }
     pub fn tx_with_size(size: u8, payload_size: PayloadSize) -> Self {
         let mut fifo = Self::rx_with_size(size, payload_size);
         fifo.transmit = true;
+        fifo.interrupts = FIFOInterrupts::default();
         fifo
     }
 }
@@ -94,6 +394,28 @@ impl<const M: u8> MaskConfig<M> {
 pub enum Clock {
     Clock20MHz,
     Clock40MHz,
+    /// 4 MHz oscillator multiplied up to 40 MHz by the PLL, then divided by 2
+    Clock20MHzPLL,
+    /// 4 MHz oscillator multiplied up to 40 MHz by the PLL
+    Clock40MHzPLL,
+}
+impl Clock {
+    /// System clock frequency in Hz
+    pub const fn frequency(&self) -> u32 {
+        match self {
+            Clock::Clock20MHz | Clock::Clock20MHzPLL => 20_000_000,
+            Clock::Clock40MHz | Clock::Clock40MHzPLL => 40_000_000,
+        }
+    }
+    pub(crate) const fn pll_enabled(&self) -> bool {
+        matches!(self, Clock::Clock20MHzPLL | Clock::Clock40MHzPLL)
+    }
+    pub(crate) const fn divisor(&self) -> ClockDivisor {
+        match self {
+            Clock::Clock20MHzPLL => ClockDivisor::DivideBy2,
+            _ => ClockDivisor::DivideBy1,
+        }
+    }
 }
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -130,28 +452,228 @@ impl Default for BitRate {
         Self { arbitration: ArbitrationBitRate::Rate500K, data: DataBitRate::Rate2M }
     }
 }
-#[derive(Default)]
+/// Bit timing used for the arbitration and data phases
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitTiming {
+    /// One of the predefined arbitration/data bit rate pairs
+    Preset(BitRate),
+    /// A named profile from a published bit timing recommendation
+    Profile(BusProfile),
+    /// Raw segment values, written to the controller as-is
+    Raw(BitRateConfig),
+}
+impl Default for BitTiming {
+    fn default() -> Self {
+        Self::Preset(BitRate::default())
+    }
+}
+impl From<BitRate> for BitTiming {
+    fn from(bit_rate: BitRate) -> Self {
+        Self::Preset(bit_rate)
+    }
+}
+impl From<BusProfile> for BitTiming {
+    fn from(profile: BusProfile) -> Self {
+        Self::Profile(profile)
+    }
+}
+impl From<BitRateConfig> for BitTiming {
+    fn from(config: BitRateConfig) -> Self {
+        Self::Raw(config)
+    }
+}
+impl BitTiming {
+    pub(crate) fn get_config(&self, clock: &Clock) -> Option<BitRateConfig> {
+        match self {
+            BitTiming::Preset(bit_rate) => bit_rate.get_config(clock),
+            BitTiming::Profile(profile) => profile.get_config(clock),
+            BitTiming::Raw(config) => Some(config.clone()),
+        }
+    }
+}
+
+/// Raw values of the `NBTCFG`, `DBTCFG` and `TDC` registers
+///
+/// Prescalers and segments use the register encoding, i.e. one less than the number of
+/// time quanta (or clocks, for the prescalers). A bit consists of the sync segment (always
+/// one time quantum), TSEG1 and TSEG2.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub(crate) struct BitRateConfig {
-    pub(crate) arbitration_brp: u8,
-    pub(crate) arbitration_tseg1: u8,
-    pub(crate) arbitration_tseg2: u8,
-    pub(crate) arbitration_sjw: u8,
-    pub(crate) data_brp: u8,
-    pub(crate) data_tseg1: u8,
-    pub(crate) data_tseg2: u8,
-    pub(crate) data_sjw: u8,
-    pub(crate) tdc_offset: u8,
-    pub(crate) tdc_value: u8,
-    pub(crate) tdc_mode: u8,
+pub struct BitRateConfig {
+    /// Nominal Baud Rate Prescaler (0 to 255)
+    pub arbitration_brp: u8,
+    /// Nominal Time Segment 1 (0 to 255)
+    pub arbitration_tseg1: u8,
+    /// Nominal Time Segment 2 (0 to 127)
+    pub arbitration_tseg2: u8,
+    /// Nominal Synchronization Jump Width (0 to 127, at most TSEG2)
+    pub arbitration_sjw: u8,
+    /// Data Baud Rate Prescaler (0 to 255)
+    pub data_brp: u8,
+    /// Data Time Segment 1 (0 to 31)
+    pub data_tseg1: u8,
+    /// Data Time Segment 2 (0 to 15)
+    pub data_tseg2: u8,
+    /// Data Synchronization Jump Width (0 to 15, at most TSEG2)
+    pub data_sjw: u8,
+    /// Transmitter Delay Compensation Offset in system clocks (-64 to 63)
+    pub tdc_offset: i8,
+    /// Transmitter Delay Compensation Value in system clocks (0 to 63)
+    pub tdc_value: u8,
+    /// Transmitter Delay Compensation Mode
+    pub tdc_mode: DelayCompensationMode,
+}
+
+impl BitRateConfig {
+    /// Checks that every value fits its register field, that neither SJW exceeds its TSEG2 and
+    /// that the delay compensation fits the data bit time
+    ///
+    /// The TDC offset must be shorter than one data bit and, in manual mode, the secondary
+    /// sample point (`tdc_value + tdc_offset`) must not lie before the start of the bit.
+    pub fn is_valid(&self) -> bool {
+        let data_bit_clocks = self.data_bit_clocks() as i16;
+        let tdc_offset = self.tdc_offset as i16;
+        let tdc_valid = match self.tdc_mode {
+            DelayCompensationMode::Disabled => true,
+            DelayCompensationMode::Manual => tdc_offset.abs() < data_bit_clocks && self.tdc_value as i16 + tdc_offset >= 0,
+            DelayCompensationMode::Auto1 | DelayCompensationMode::Auto2 => tdc_offset.abs() < data_bit_clocks,
+        };
+        self.arbitration_tseg2 <= 127
+            && self.arbitration_sjw <= self.arbitration_tseg2
+            && self.data_tseg1 <= 31
+            && self.data_tseg2 <= 15
+            && self.data_sjw <= self.data_tseg2
+            && (-64..=63).contains(&self.tdc_offset)
+            && self.tdc_value <= 63
+            && tdc_valid
+    }
+
+    /// Length of a data phase bit in system clocks
+    pub fn data_bit_clocks(&self) -> u16 {
+        (self.data_brp as u16 + 1) * (3 + self.data_tseg1 as u16 + self.data_tseg2 as u16)
+    }
+
+    pub(crate) fn nominal_bit_time_config(&self) -> NominalBitTimeConfig {
+        NominalBitTimeConfig::new()
+            .with_brp(self.arbitration_brp)
+            .with_tseg1(self.arbitration_tseg1)
+            .with_tseg2(self.arbitration_tseg2)
+            .with_sjw(self.arbitration_sjw)
+    }
+
+    pub(crate) fn data_bit_time_config(&self) -> DataBitTimeConfig {
+        DataBitTimeConfig::new()
+            .with_brp(self.data_brp)
+            .with_tseg1(self.data_tseg1)
+            .with_tseg2(self.data_tseg2)
+            .with_sjw(self.data_sjw)
+    }
+
+    /// Builds the configuration from register values read back from the controller
+    pub fn from_registers(nominal: NominalBitTimeConfig, data: DataBitTimeConfig, tdc: TransmitterDelayCompensation) -> Self {
+        Self {
+            arbitration_brp: nominal.brp(),
+            arbitration_tseg1: nominal.tseg1(),
+            arbitration_tseg2: nominal.tseg2(),
+            arbitration_sjw: nominal.sjw(),
+            data_brp: data.brp(),
+            data_tseg1: data.tseg1(),
+            data_tseg2: data.tseg2(),
+            data_sjw: data.sjw(),
+            // TDCO is a 7 bit two's complement value
+            tdc_offset: ((tdc.tdco() << 1) as i8) >> 1,
+            tdc_value: tdc.tdcv(),
+            tdc_mode: tdc.tdcmod(),
+        }
+    }
+
+    /// Decodes the raw values into bit rates, sample points and SJWs for the given system clock
+    pub fn decode(&self, clock: &Clock) -> BitTimingInfo {
+        BitTimingInfo {
+            arbitration: PhaseTiming::new(clock, self.arbitration_brp, self.arbitration_tseg1, self.arbitration_tseg2, self.arbitration_sjw),
+            data: PhaseTiming::new(clock, self.data_brp, self.data_tseg1, self.data_tseg2, self.data_sjw),
+            tdc_mode: self.tdc_mode,
+            tdc_offset: self.tdc_offset,
+            tdc_value: self.tdc_value,
+        }
+    }
+}
+
+/// Decoded timing of a single bit rate phase
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PhaseTiming {
+    /// Bit rate in bits per second (rounded down)
+    pub bit_rate: u32,
+    /// Number of time quanta per bit
+    pub time_quanta: u16,
+    /// Sample point in tenths of a percent of the bit time (e.g. 800 = 80.0%)
+    pub sample_point: u16,
+    /// Synchronization Jump Width in time quanta
+    pub sjw: u8,
+}
+impl PhaseTiming {
+    fn new(clock: &Clock, brp: u8, tseg1: u8, tseg2: u8, sjw: u8) -> Self {
+        let tseg1 = tseg1 as u16 + 1;
+        let tseg2 = tseg2 as u16 + 1;
+        let time_quanta = 1 + tseg1 + tseg2;
+        Self {
+            bit_rate: clock.frequency() / ((brp as u32 + 1) * time_quanta as u32),
+            time_quanta,
+            sample_point: ((1 + tseg1) as u32 * 1000 / time_quanta as u32) as u16,
+            sjw: sjw + 1,
+        }
+    }
+}
+
+/// Decoded bit timing of the arbitration and data phases
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BitTimingInfo {
+    pub arbitration: PhaseTiming,
+    pub data: PhaseTiming,
+    /// Transmitter Delay Compensation Mode
+    pub tdc_mode: DelayCompensationMode,
+    /// Transmitter Delay Compensation Offset in system clocks
+    pub tdc_offset: i8,
+    /// Transmitter Delay Compensation Value in system clocks
+    /// In automatic mode, this is the delay measured by the controller
+    pub tdc_value: u8,
 }
 
 impl BitRate {
-    pub(crate) fn get_config(&self, clock: &Clock) -> Option<BitRateConfig> {
-        let mut config = BitRateConfig::default();
-        config.tdc_mode = 0b10; // Auto mode
+    /// Every arbitration and data bit rate pair, e.g. as candidates for
+    /// [`MCP25xxFD::detect_bit_rate`](crate::MCP25xxFD::detect_bit_rate)
+    pub fn presets() -> impl Iterator<Item = BitRate> {
+        const ARBITRATION: [ArbitrationBitRate; 4] = [
+            ArbitrationBitRate::Rate125K,
+            ArbitrationBitRate::Rate250K,
+            ArbitrationBitRate::Rate500K,
+            ArbitrationBitRate::Rate1000K,
+        ];
+        const DATA: [DataBitRate; 11] = [
+            DataBitRate::Rate500K,
+            DataBitRate::Rate833K,
+            DataBitRate::Rate1M,
+            DataBitRate::Rate1M5,
+            DataBitRate::Rate2M,
+            DataBitRate::Rate3M,
+            DataBitRate::Rate4M,
+            DataBitRate::Rate5M,
+            DataBitRate::Rate6M7,
+            DataBitRate::Rate8M,
+            DataBitRate::Rate10M,
+        ];
+        ARBITRATION.into_iter().flat_map(|arbitration| {
+            DATA.into_iter().map(move |data| BitRate { arbitration: arbitration.clone(), data })
+        })
+    }
+
+    pub fn get_config(&self, clock: &Clock) -> Option<BitRateConfig> {
+        let mut config = BitRateConfig { tdc_mode: DelayCompensationMode::Auto1, ..Default::default() };
         match clock {
-            Clock::Clock40MHz => {
+            Clock::Clock40MHz | Clock::Clock40MHzPLL => {
                 match &self.arbitration {
                     ArbitrationBitRate::Rate125K => {
                         config.arbitration_brp = 0;
@@ -253,7 +775,7 @@ impl BitRate {
                         config.data_sjw = 7;
                         config.tdc_offset = 31;
                         config.tdc_value = 0;
-                        config.tdc_mode = 0;
+                        config.tdc_mode = DelayCompensationMode::Disabled;
                     },
                     (ArbitrationBitRate::Rate250K, DataBitRate::Rate833K) => {
                         config.data_brp = 1;
@@ -262,7 +784,7 @@ impl BitRate {
                         config.data_sjw = 4;
                         config.tdc_offset = 18;
                         config.tdc_value = 0;
-                        config.tdc_mode = 0;
+                        config.tdc_mode = DelayCompensationMode::Disabled;
                     },
                     (ArbitrationBitRate::Rate250K, DataBitRate::Rate1M) => {
                         config.data_brp = 0;
@@ -307,7 +829,7 @@ impl BitRate {
                     _ => { return None },
                 };
             },
-            Clock::Clock20MHz => {
+            Clock::Clock20MHz | Clock::Clock20MHzPLL => {
                 match &self.arbitration {
                     ArbitrationBitRate::Rate125K => {
                         config.arbitration_brp = 0;
@@ -376,7 +898,7 @@ impl BitRate {
                         config.data_sjw = 7;
                         config.tdc_offset = 31;
                         config.tdc_value = 0;
-                        config.tdc_mode = 0;
+                        config.tdc_mode = DelayCompensationMode::Disabled;
                     },
                     (ArbitrationBitRate::Rate250K, DataBitRate::Rate833K) => {
                         config.data_brp = 0;
@@ -385,7 +907,7 @@ impl BitRate {
                         config.data_sjw = 4;
                         config.tdc_offset = 18;
                         config.tdc_value = 0;
-                        config.tdc_mode = 0;
+                        config.tdc_mode = DelayCompensationMode::Disabled;
                     },
                     (ArbitrationBitRate::Rate250K, DataBitRate::Rate1M) => {
                         config.data_brp = 0;
@@ -426,4 +948,95 @@ impl BitRate {
 
         Some(config)
     }
-}
\ No newline at end of file
+}
+/// Bit timing profiles reproducing published CAN FD system design recommendations
+///
+/// All profiles use the same prescaler (1) in both phases, set SJW equal to TSEG2 and enable
+/// automatic transmitter delay compensation with the secondary sample point placed at the data
+/// phase sample point.
+///
+/// | Profile                   | Arbitration         | Data              |
+/// |---------------------------|---------------------|-------------------|
+/// | [`BusProfile::SaeJ2284_4`] | 500 kbit/s, 80 %    | 2 Mbit/s, 75 %    |
+/// | [`BusProfile::SaeJ2284_5`] | 500 kbit/s, 80 %    | 5 Mbit/s, 75 %    |
+/// | [`BusProfile::CiA601_3_2M`] | 500 kbit/s, 80 %   | 2 Mbit/s, 70 %    |
+/// | [`BusProfile::CiA601_3_5M`] | 500 kbit/s, 80 %   | 5 Mbit/s, 75 %    |
+///
+/// [`BusProfile::SaeJ2284_4`] is unavailable with a 20 MHz system clock and returns `None`. A
+/// 2 Mbit/s bit is then 10 system clocks long, and as the time quantum can't be shorter than a
+/// clock, the sample point can only be placed at 70 % or 80 % of the bit, never at 75 %.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(non_camel_case_types)]
+pub enum BusProfile {
+    /// SAE J2284-4, 500 kbit/s arbitration with 2 Mbit/s data phase
+    SaeJ2284_4,
+    /// SAE J2284-5, 500 kbit/s arbitration with 5 Mbit/s data phase
+    SaeJ2284_5,
+    /// CiA 601-3, 500 kbit/s arbitration with 2 Mbit/s data phase
+    CiA601_3_2M,
+    /// CiA 601-3, 500 kbit/s arbitration with 5 Mbit/s data phase
+    CiA601_3_5M,
+}
+
+impl BusProfile {
+    pub fn get_config(&self, clock: &Clock) -> Option<BitRateConfig> {
+        let mut config = BitRateConfig {
+            tdc_mode: DelayCompensationMode::Auto1,
+            ..Default::default()
+        };
+        match clock {
+            Clock::Clock40MHz | Clock::Clock40MHzPLL => {
+                // 500 kbit/s: 80 time quanta, sample point 64/80
+                config.arbitration_tseg1 = 62;
+                config.arbitration_tseg2 = 15;
+                config.arbitration_sjw = 15;
+                match self {
+                    BusProfile::SaeJ2284_4 => {
+                        // 2 Mbit/s: 20 time quanta, sample point 15/20
+                        config.data_tseg1 = 13;
+                        config.data_tseg2 = 4;
+                        config.data_sjw = 4;
+                    },
+                    BusProfile::CiA601_3_2M => {
+                        // 2 Mbit/s: 20 time quanta, sample point 14/20
+                        config.data_tseg1 = 12;
+                        config.data_tseg2 = 5;
+                        config.data_sjw = 5;
+                    },
+                    BusProfile::SaeJ2284_5 | BusProfile::CiA601_3_5M => {
+                        // 5 Mbit/s: 8 time quanta, sample point 6/8
+                        config.data_tseg1 = 4;
+                        config.data_tseg2 = 1;
+                        config.data_sjw = 1;
+                    },
+                }
+            },
+            Clock::Clock20MHz | Clock::Clock20MHzPLL => {
+                // 500 kbit/s: 40 time quanta, sample point 32/40
+                config.arbitration_tseg1 = 30;
+                config.arbitration_tseg2 = 7;
+                config.arbitration_sjw = 7;
+                match self {
+                    BusProfile::SaeJ2284_4 => { return None },
+                    BusProfile::CiA601_3_2M => {
+                        // 2 Mbit/s: 10 time quanta, sample point 7/10
+                        config.data_tseg1 = 5;
+                        config.data_tseg2 = 2;
+                        config.data_sjw = 2;
+                    },
+                    BusProfile::SaeJ2284_5 | BusProfile::CiA601_3_5M => {
+                        // 5 Mbit/s: 4 time quanta, sample point 3/4
+                        config.data_tseg1 = 1;
+                        config.data_tseg2 = 0;
+                        config.data_sjw = 0;
+                    },
+                }
+            },
+        }
+        // Secondary sample point at the data phase sample point
+        config.tdc_offset = (config.data_tseg1 + 1) as i8;
+
+        Some(config)
+    }
+}
@@ -0,0 +1,133 @@
+use embedded_hal_async::spi::{Operation, SpiDevice};
+use crate::interrupt::InterruptPins;
+use crate::registers::*;
+use crate::{Error, Instruction, MCP25xxFD};
+
+// Flag bits in the third byte of CRC
+const CRC_CRCERRIF: u8 = 1 << 0;
+const CRC_FERRIF: u8 = 1 << 1;
+
+/// CRC-16 used by the `ReadCRC`, `WriteCRC` and `WriteSafe` instructions
+///
+/// Polynomial 0x8005, seeded with 0xFFFF, without reflection or final XOR.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Errors flagged in `CRC` since it was last cleared
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SPICRCEvent {
+    /// An instruction's CRC didn't match its contents
+    pub crc_error: bool,
+    /// An instruction had the wrong number of bytes
+    pub format_error: bool,
+    /// CRC received with the last mismatch
+    pub crc: u16,
+}
+
+/// Number of SPI errors seen by the driver
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SPICRCCounters {
+    /// CRC errors flagged by the controller
+    pub crc_errors: u32,
+    /// Format errors flagged by the controller
+    pub format_errors: u32,
+    /// Mismatched CRCs in responses to [`MCP25xxFD::read_register_crc`]
+    pub read_errors: u32,
+    /// Writes repeated by [`MCP25xxFD::write_register_safe`]
+    pub write_retries: u32,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Check `CRC` for errors the controller detected, returning `None` if there are none
+    ///
+    /// The flags are cleared and the counters updated.
+    pub async fn check_spi_crc(&mut self) -> Result<Option<SPICRCEvent>, Error<SPI>> {
+        let status: CRCStatus = self.read_register().await?;
+        if !status.crcerrif() && !status.ferrif() {
+            return Ok(None);
+        }
+
+        let mut flags = 0;
+        if status.crcerrif() {
+            flags |= CRC_CRCERRIF;
+            self.crc_counters.crc_errors = self.crc_counters.crc_errors.saturating_add(1);
+        }
+        if status.ferrif() {
+            flags |= CRC_FERRIF;
+            self.crc_counters.format_errors = self.crc_counters.format_errors.saturating_add(1);
+        }
+        // Writing 1 to a flag leaves it unchanged
+        self.write_register_byte(CRCStatus::ADDRESS + 2, !flags).await?;
+
+        Ok(Some(SPICRCEvent {
+            crc_error: status.crcerrif(),
+            format_error: status.ferrif(),
+            crc: status.crc(),
+        }))
+    }
+
+    /// SPI errors counted since the driver was created or the counters were last cleared
+    pub fn spi_crc_counters(&self) -> SPICRCCounters {
+        self.crc_counters
+    }
+
+    pub fn clear_spi_crc_counters(&mut self) {
+        self.crc_counters = SPICRCCounters::default();
+    }
+
+    /// Read a single register, checking the CRC the controller sends along with it
+    pub async fn read_register_crc<R: Register>(&mut self) -> Result<R, Error<SPI>> {
+        let mut tx = [0u8; 3];
+        tx[..2].copy_from_slice(&Instruction::ReadCRC.header(R::ADDRESS));
+        tx[2] = 4;
+        let mut rx = [0u8; 6];
+        self.spi.transaction(&mut [
+            Operation::Write(&tx),
+            Operation::Read(&mut rx),
+        ]).await.map_err(Error::SPIError)?;
+
+        let mut message = [0u8; 7];
+        message[..3].copy_from_slice(&tx);
+        message[3..].copy_from_slice(&rx[..4]);
+        if crc16(&message) != u16::from_be_bytes([rx[4], rx[5]]) {
+            self.crc_counters.read_errors = self.crc_counters.read_errors.saturating_add(1);
+            return Err(Error::ControllerError("CRC mismatch in SPI response"));
+        }
+        Ok(R::parse(&rx[..4]))
+    }
+
+    /// Write a single register with the `WriteSafe` instruction
+    ///
+    /// The controller only performs the write if the CRC matches. A rejected write is repeated up to
+    /// [`SPICRCConfig::write_retries`] times.
+    ///
+    /// [`SPICRCConfig::write_retries`]: crate::config::SPICRCConfig::write_retries
+    pub async fn write_register_safe<R: Register>(&mut self, register: R) -> Result<(), Error<SPI>> {
+        let mut tx = [0u8; 8];
+        tx[..2].copy_from_slice(&Instruction::WriteSafe.header(R::ADDRESS));
+        tx[2..6].copy_from_slice(&register.serialize());
+        let crc = crc16(&tx[..6]);
+        tx[6..].copy_from_slice(&crc.to_be_bytes());
+
+        for attempt in 0..=self.crc_write_retries {
+            if attempt > 0 {
+                self.crc_counters.write_retries = self.crc_counters.write_retries.saturating_add(1);
+            }
+            self.spi.write(&tx).await.map_err(Error::SPIError)?;
+            if self.check_spi_crc().await?.is_none() {
+                return Ok(());
+            }
+        }
+        Err(Error::ControllerError("Write rejected because of SPI CRC errors"))
+    }
+}
@@ -0,0 +1,333 @@
+use core::fmt::{Debug, Formatter};
+use embedded_hal_async::spi::{Operation, SpiDevice};
+use crate::interrupt::InterruptPins;
+use crate::registers::*;
+use crate::{Error, Instruction, MCP25xxFD};
+
+/// First and last address of the CAN FD controller SFRs
+const CAN_START: u16 = 0x000;
+const CAN_END: u16 = 0x2EC;
+const CAN_WORDS: usize = (CAN_END - CAN_START) as usize / 4 + 1;
+/// First and last address of the oscillator, I/O, CRC and ECC SFRs
+const DEVICE_START: u16 = 0xE00;
+const DEVICE_END: u16 = 0xE14;
+const DEVICE_WORDS: usize = (DEVICE_END - DEVICE_START) as usize / 4 + 1;
+
+const FIFO_START: u16 = 0x05C;
+const FIFO_END: u16 = 0x1CC;
+const FILTER_CONTROL_START: u16 = 0x1D0;
+const FILTER_CONTROL_END: u16 = 0x1EC;
+const FILTER_START: u16 = 0x1F0;
+
+type Decoder = fn(u32, &mut Formatter<'_>) -> core::fmt::Result;
+
+fn decode<R: Register>(value: u32, f: &mut Formatter<'_>) -> core::fmt::Result
+where
+    R::Bitfield: Debug,
+{
+    R::parse(&value.to_le_bytes()).into_bitfield().fmt(f)
+}
+
+/// Name of an SFR as in the datasheet, with the FIFO, filter or filter control register number
+/// for registers that come in sets
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct RegisterName {
+    pub name: &'static str,
+    pub index: Option<u8>,
+}
+impl RegisterName {
+    const fn fixed(name: &'static str) -> Self {
+        Self { name, index: None }
+    }
+    const fn indexed(name: &'static str, index: u16) -> Self {
+        Self { name, index: Some(index as u8) }
+    }
+
+    /// The register at `address` and how to decode it, `None` for reserved addresses
+    fn lookup(address: u16) -> Option<(Self, Decoder)> {
+        Some(match address {
+            CANControl::ADDRESS => (Self::fixed("CiCON"), decode::<CANControl>),
+            NominalBitTimeConfig::ADDRESS => (Self::fixed("CiNBTCFG"), decode::<NominalBitTimeConfig>),
+            DataBitTimeConfig::ADDRESS => (Self::fixed("CiDBTCFG"), decode::<DataBitTimeConfig>),
+            TransmitterDelayCompensation::ADDRESS => (Self::fixed("CiTDC"), decode::<TransmitterDelayCompensation>),
+            TimeBaseCounter::ADDRESS => (Self::fixed("CiTBC"), decode::<TimeBaseCounter>),
+            TimeStampControl::ADDRESS => (Self::fixed("CiTSCON"), decode::<TimeStampControl>),
+            InterruptCode::ADDRESS => (Self::fixed("CiVEC"), decode::<InterruptCode>),
+            Interrupts::ADDRESS => (Self::fixed("CiINT"), decode::<Interrupts>),
+            ReceiveInterruptStatus::ADDRESS => (Self::fixed("CiRXIF"), decode::<ReceiveInterruptStatus>),
+            TransmitInterruptStatus::ADDRESS => (Self::fixed("CiTXIF"), decode::<TransmitInterruptStatus>),
+            ReceiveOverflowInterruptStatus::ADDRESS => (Self::fixed("CiRXOVIF"), decode::<ReceiveOverflowInterruptStatus>),
+            TransmitAttemptInterruptStatus::ADDRESS => (Self::fixed("CiTXATIF"), decode::<TransmitAttemptInterruptStatus>),
+            TransmitRequest::ADDRESS => (Self::fixed("CiTXREQ"), decode::<TransmitRequest>),
+            TransmitReceiveErrorCount::ADDRESS => (Self::fixed("CiTREC"), decode::<TransmitReceiveErrorCount>),
+            BusDiagnostic0::ADDRESS => (Self::fixed("CiBDIAG0"), decode::<BusDiagnostic0>),
+            BusDiagnostic1::ADDRESS => (Self::fixed("CiBDIAG1"), decode::<BusDiagnostic1>),
+            TransmitEventFIFOControl::ADDRESS => (Self::fixed("CiTEFCON"), decode::<TransmitEventFIFOControl>),
+            TransmitEventFIFOStatus::ADDRESS => (Self::fixed("CiTEFSTA"), decode::<TransmitEventFIFOStatus>),
+            TransmitEventFIFOUserAddress::ADDRESS => (Self::fixed("CiTEFUA"), decode::<TransmitEventFIFOUserAddress>),
+            TransmitQueueControl::ADDRESS => (Self::fixed("CiTXQCON"), decode::<TransmitQueueControl>),
+            TransmitQueueStatus::ADDRESS => (Self::fixed("CiTXQSTA"), decode::<TransmitQueueStatus>),
+            TransmitQueueUserAddress::ADDRESS => (Self::fixed("CiTXQUA"), decode::<TransmitQueueUserAddress>),
+            FIFO_START..=FIFO_END => {
+                let fifo = (address - FIFO_START) / 12 + 1;
+                match (address - FIFO_START) % 12 {
+                    0 => (Self::indexed("CiFIFOCON", fifo), decode::<FIFOControl<1>>),
+                    4 => (Self::indexed("CiFIFOSTA", fifo), decode::<FIFOStatus<1>>),
+                    _ => (Self::indexed("CiFIFOUA", fifo), decode::<FIFOUserAddress<1>>),
+                }
+            },
+            FILTER_CONTROL_START..=FILTER_CONTROL_END => {
+                (Self::indexed("CiFLTCON", (address - FILTER_CONTROL_START) / 4), decode::<FilterControl<0>>)
+            },
+            FILTER_START..=CAN_END => {
+                let filter = (address - FILTER_START) / 8;
+                match (address - FILTER_START) % 8 {
+                    0 => (Self::indexed("CiFLTOBJ", filter), decode::<FilterObject<0>>),
+                    _ => (Self::indexed("CiMASK", filter), decode::<Mask<0>>),
+                }
+            },
+            OscillatorControl::ADDRESS => (Self::fixed("OSC"), decode::<OscillatorControl>),
+            IOControl::ADDRESS => (Self::fixed("IOCON"), decode::<IOControl>),
+            CRCStatus::ADDRESS => (Self::fixed("CRC"), decode::<CRCStatus>),
+            ECCControl::ADDRESS => (Self::fixed("ECCCON"), decode::<ECCControl>),
+            ECCStatus::ADDRESS => (Self::fixed("ECCSTAT"), decode::<ECCStatus>),
+            DeviceID::ADDRESS => (Self::fixed("DEVID"), decode::<DeviceID>),
+            _ => return None,
+        })
+    }
+}
+impl Debug for RegisterName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name)?;
+        match self.index {
+            Some(index) => write!(f, "{}", index),
+            None => Ok(()),
+        }
+    }
+}
+#[cfg(feature = "defmt")]
+impl defmt::Format for RegisterName {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self.index {
+            Some(index) => defmt::write!(fmt, "{=str}{=u8}", self.name, index),
+            None => defmt::write!(fmt, "{=str}", self.name),
+        }
+    }
+}
+
+/// Register value shown through the bitfield it belongs to
+struct Decoded(Decoder, u32);
+impl Debug for Decoded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        (self.0)(self.1, f)
+    }
+}
+
+/// Contents of every SFR, read with [`MCP25xxFD::dump_registers`]
+///
+/// `Debug` decodes each register into its bitfield, `defmt` shows raw values to keep the output
+/// short.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RegisterDump {
+    can: [u32; CAN_WORDS],
+    device: [u32; DEVICE_WORDS],
+}
+impl RegisterDump {
+    /// Raw value of the register at `address`, `None` outside of the SFR blocks
+    pub fn raw(&self, address: u16) -> Option<u32> {
+        if address % 4 != 0 {
+            return None;
+        }
+        match address {
+            CAN_START..=CAN_END => Some(self.can[(address - CAN_START) as usize / 4]),
+            DEVICE_START..=DEVICE_END => Some(self.device[(address - DEVICE_START) as usize / 4]),
+            _ => None,
+        }
+    }
+
+    /// Decoded register `R`
+    pub fn register<R: Register>(&self) -> R {
+        let value = self.raw(R::ADDRESS).expect("Register outside of the SFR blocks");
+        R::parse(&value.to_le_bytes())
+    }
+
+    /// `CiFIFOCONm` of FIFO `fifo` (1 to 31)
+    pub fn fifo_control(&self, fifo: u8) -> Option<FIFOControlM> {
+        self.fifo_register::<FIFOControl<1>>(fifo, 0)
+    }
+    /// `CiFIFOSTAm` of FIFO `fifo` (1 to 31)
+    pub fn fifo_status(&self, fifo: u8) -> Option<FIFOStatusM> {
+        self.fifo_register::<FIFOStatus<1>>(fifo, 4)
+    }
+    /// `CiFIFOUAm` of FIFO `fifo` (1 to 31)
+    pub fn fifo_user_address(&self, fifo: u8) -> Option<FIFOUserAddressM> {
+        self.fifo_register::<FIFOUserAddress<1>>(fifo, 8)
+    }
+    fn fifo_register<R: Register>(&self, fifo: u8, offset: u16) -> Option<R::Bitfield> {
+        if !(1..=31).contains(&fifo) {
+            return None;
+        }
+        self.decode_at::<R>(FIFO_START + 12 * (fifo as u16 - 1) + offset)
+    }
+
+    /// `CiFLTCONm` holding filters `4 * register` to `4 * register + 3` (register 0 to 7)
+    pub fn filter_control(&self, register: u8) -> Option<FilterControlM> {
+        if register >= 8 {
+            return None;
+        }
+        self.decode_at::<FilterControl<0>>(FILTER_CONTROL_START + 4 * register as u16)
+    }
+    /// `CiFLTOBJm` of filter `filter` (0 to 31)
+    pub fn filter_object(&self, filter: u8) -> Option<FilterObjectM> {
+        if filter >= 32 {
+            return None;
+        }
+        self.decode_at::<FilterObject<0>>(FILTER_START + 8 * filter as u16)
+    }
+    /// `CiMASKm` of filter `filter` (0 to 31)
+    pub fn mask(&self, filter: u8) -> Option<MaskM> {
+        if filter >= 32 {
+            return None;
+        }
+        self.decode_at::<Mask<0>>(FILTER_START + 8 * filter as u16 + 4)
+    }
+
+    fn decode_at<R: Register>(&self, address: u16) -> Option<R::Bitfield> {
+        let value = self.raw(address)?;
+        Some(R::parse(&value.to_le_bytes()).into_bitfield())
+    }
+
+    /// Name, address and raw value of every register, skipping reserved addresses
+    pub fn registers(&self) -> impl Iterator<Item = (RegisterName, u16, u32)> + '_ {
+        self.entries().map(|(name, address, value, _)| (name, address, value))
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (RegisterName, u16, u32, Decoder)> + '_ {
+        let can = (CAN_START..=CAN_END).step_by(4);
+        let device = (DEVICE_START..=DEVICE_END).step_by(4);
+        can.chain(device).filter_map(|address| {
+            let (name, decoder) = RegisterName::lookup(address)?;
+            Some((name, address, self.raw(address)?, decoder))
+        })
+    }
+
+    /// Registers that differ between `self` and the later dump `other`
+    pub fn diff<'a>(&'a self, other: &'a RegisterDump) -> RegisterDiff<'a> {
+        RegisterDiff { before: self, after: other }
+    }
+}
+impl Debug for RegisterDump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mut map = f.debug_map();
+        for (name, _, value, decoder) in self.entries() {
+            map.entry(&name, &Decoded(decoder, value));
+        }
+        map.finish()
+    }
+}
+#[cfg(feature = "defmt")]
+impl defmt::Format for RegisterDump {
+    fn format(&self, fmt: defmt::Formatter) {
+        for (name, address, value) in self.registers() {
+            defmt::write!(fmt, "{} ({=u16:#05x}): {=u32:#010x}\n", name, address, value);
+        }
+    }
+}
+
+/// A register that changed between two dumps
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub name: RegisterName,
+    pub address: u16,
+    pub before: u32,
+    pub after: u32,
+}
+impl RegisterChange {
+    /// Bits that flipped
+    pub fn changed_bits(&self) -> u32 {
+        self.before ^ self.after
+    }
+}
+impl Debug for RegisterChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let (_, decoder) = RegisterName::lookup(self.address).unwrap();
+        f.debug_struct("RegisterChange")
+            .field("name", &self.name)
+            .field("address", &format_args!("{:#05x}", self.address))
+            .field("changed_bits", &format_args!("{:#010x}", self.changed_bits()))
+            .field("before", &Decoded(decoder, self.before))
+            .field("after", &Decoded(decoder, self.after))
+            .finish()
+    }
+}
+#[cfg(feature = "defmt")]
+impl defmt::Format for RegisterChange {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "{} ({=u16:#05x}): {=u32:#010x} -> {=u32:#010x}",
+            self.name, self.address, self.before, self.after,
+        );
+    }
+}
+
+/// Changes between two [`RegisterDump`]s
+#[derive(Copy, Clone)]
+pub struct RegisterDiff<'a> {
+    before: &'a RegisterDump,
+    after: &'a RegisterDump,
+}
+impl<'a> RegisterDiff<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = RegisterChange> + 'a {
+        let after = self.after;
+        self.before.registers().filter_map(move |(name, address, before)| {
+            let after = after.raw(address)?;
+            (before != after).then_some(RegisterChange { name, address, before, after })
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+impl Debug for RegisterDiff<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+#[cfg(feature = "defmt")]
+impl defmt::Format for RegisterDiff<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        for change in self.iter() {
+            defmt::write!(fmt, "{}\n", change);
+        }
+    }
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Read every SFR in two burst reads
+    ///
+    /// Reading has no side effects, so this can be done in any mode. The registers keep changing
+    /// while the controller is running, so the dump isn't an atomic snapshot.
+    pub async fn dump_registers(&mut self) -> Result<RegisterDump, Error<SPI>> {
+        let mut dump = RegisterDump { can: [0; CAN_WORDS], device: [0; DEVICE_WORDS] };
+        let mut can = [0u8; CAN_WORDS * 4];
+        self.read_block(CAN_START, &mut can).await?;
+        let mut device = [0u8; DEVICE_WORDS * 4];
+        self.read_block(DEVICE_START, &mut device).await?;
+
+        for (word, bytes) in dump.can.iter_mut().zip(can.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        for (word, bytes) in dump.device.iter_mut().zip(device.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        Ok(dump)
+    }
+
+    async fn read_block(&mut self, address: u16, data: &mut [u8]) -> Result<(), Error<SPI>> {
+        self.spi.transaction(&mut [
+            Operation::Write(&Instruction::Read.header(address)),
+            Operation::Read(data),
+        ]).await.map_err(Error::SPIError)
+    }
+}
@@ -0,0 +1,206 @@
+use core::ops::Range;
+use embedded_hal_async::spi::SpiDevice;
+use crate::interrupt::InterruptPins;
+use crate::registers::*;
+use crate::{fifo_control_address, Error, MCP25xxFD, RAM_SIZE, RAM_START};
+
+// Flag bits of ECCSTAT
+const ECC_SECIF: u8 = 1 << 1;
+const ECC_DEDIF: u8 = 1 << 2;
+
+// FRESET bit in the second byte of CiTEFCON, CiTXQCON and CiFIFOCONm
+const FIFO_FRESET: u8 = 1 << 2;
+
+/// Severity of an error found in message RAM
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ECCErrorKind {
+    /// Single bit error, corrected on the fly when the word was read
+    SingleBit,
+    /// Double bit error, the data read from RAM is corrupt
+    DoubleBit,
+}
+
+/// Owner of a message RAM address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RAMLocation {
+    /// Message object `object` of the Transmit Event FIFO
+    TransmitEventFIFO { object: u8 },
+    /// Message object `object` of the TXQ
+    TransmitQueue { object: u8 },
+    /// Message object `object` of FIFO `fifo`
+    FIFO { fifo: u8, object: u8 },
+    /// RAM beyond the last FIFO
+    Unallocated,
+}
+
+/// ECC error reported by the controller
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ECCEvent {
+    pub kind: ECCErrorKind,
+    /// Address of the failing RAM word, as reported in `ECCSTAT.ERRADDR`
+    pub address: u16,
+    /// FIFO and message object the address belongs to with the current FIFO configuration
+    pub location: RAMLocation,
+}
+
+/// Number of ECC errors seen by [`MCP25xxFD::check_ecc`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ECCCounters {
+    pub single_bit: u32,
+    pub double_bit: u32,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Check `ECCSTAT` for an ECC error, returning `None` if there is none
+    ///
+    /// The flags are cleared and the counters updated. The controller only keeps the address of the
+    /// last error, so when both a single and a double bit error are pending a single
+    /// [`ECCErrorKind::DoubleBit`] event is returned, but both are counted.
+    ///
+    /// `ECCSTAT` doesn't show up in `CiVEC`; with [`InterruptConfig::ecc`] set, the `ECCIF` flag
+    /// in `CiINT` tells when this is worth calling.
+    ///
+    /// [`InterruptConfig::ecc`]: crate::config::InterruptConfig::ecc
+    pub async fn check_ecc(&mut self) -> Result<Option<ECCEvent>, Error<SPI>> {
+        let status: ECCStatus = self.read_register().await?;
+        if !status.secie() && !status.dedie() {
+            return Ok(None);
+        }
+
+        let mut flags = 0;
+        if status.secie() {
+            flags |= ECC_SECIF;
+            self.ecc_counters.single_bit = self.ecc_counters.single_bit.saturating_add(1);
+        }
+        if status.dedie() {
+            flags |= ECC_DEDIF;
+            self.ecc_counters.double_bit = self.ecc_counters.double_bit.saturating_add(1);
+        }
+        // Writing 1 to a flag leaves it unchanged
+        self.write_register_byte(ECCStatus::ADDRESS, !flags).await?;
+
+        let kind = if status.dedie() { ECCErrorKind::DoubleBit } else { ECCErrorKind::SingleBit };
+        let address = status.erraddr();
+        let location = self.ram_location(address).await?;
+        Ok(Some(ECCEvent { kind, address, location }))
+    }
+
+    /// ECC errors counted since the driver was created or the counters were last cleared
+    pub fn ecc_counters(&self) -> ECCCounters {
+        self.ecc_counters
+    }
+
+    pub fn clear_ecc_counters(&mut self) {
+        self.ecc_counters = ECCCounters::default();
+    }
+
+    /// Reset the FIFO owning the RAM word that failed
+    ///
+    /// Any message in the FIFO is lost, pending transmissions included, but the corrupt message
+    /// object is not read or sent. Single bit errors were already corrected and don't need this.
+    pub async fn recover_ecc_error(&mut self, event: &ECCEvent) -> Result<(), Error<SPI>> {
+        let control_address = match event.location {
+            RAMLocation::TransmitEventFIFO { .. } => TransmitEventFIFOControl::ADDRESS,
+            RAMLocation::TransmitQueue { .. } => TransmitQueueControl::ADDRESS,
+            RAMLocation::FIFO { fifo, .. } => fifo_control_address(fifo),
+            RAMLocation::Unallocated => return Err(Error::ControllerError("ECC error address is not owned by a FIFO")),
+        };
+        self.reset_fifo_at(control_address).await
+    }
+
+    /// Work out which FIFO and message object own `address`
+    ///
+    /// Message RAM is allocated in order to the TEF, the TXQ, and then FIFOs 1 to 31, so this reads
+    /// back the size of each of them. Outside of Configuration mode, where the sizes can't change,
+    /// the layout is kept until the driver changes mode or resets the controller.
+    pub async fn ram_location(&mut self, address: u16) -> Result<RAMLocation, Error<SPI>> {
+        if !(RAM_START..RAM_START + RAM_SIZE).contains(&address) {
+            return Ok(RAMLocation::Unallocated);
+        }
+        let offset = address - RAM_START;
+        let layout = self.ram_layout().await?;
+        let Some(index) = (0..33).find(|&index| layout.range(index).contains(&offset)) else {
+            return Ok(RAMLocation::Unallocated);
+        };
+        let object = ((offset - layout.starts[index]) / layout.object_sizes[index]) as u8;
+        Ok(match index {
+            0 => RAMLocation::TransmitEventFIFO { object },
+            1 => RAMLocation::TransmitQueue { object },
+            _ => RAMLocation::FIFO { fifo: index as u8 - 1, object },
+        })
+    }
+
+    /// Read back the RAM layout, or take the one cached outside of Configuration mode
+    pub(crate) async fn ram_layout(&mut self) -> Result<RAMLayout, Error<SPI>> {
+        if let Some(layout) = self.ram_layout {
+            return Ok(layout);
+        }
+        let mut layout = RAMLayout { starts: [0; 34], object_sizes: [8; 33] };
+        let mut fsizes = [0; 33];
+
+        let can_control: CANControl = self.read_register().await?;
+        if can_control.stef() {
+            let tef: TransmitEventFIFOControl = self.read_register().await?;
+            fsizes[0] = tef.fsize() + 1;
+            layout.object_sizes[0] = 8 + if tef.teftsen() { 4 } else { 0 };
+        }
+        if can_control.txqen() {
+            let txq: TransmitQueueControl = self.read_register().await?;
+            fsizes[1] = txq.fsize() + 1;
+            layout.object_sizes[1] = 8 + txq.plsize().bytes() as u16;
+        }
+        for fifo in 1..32 {
+            let data = self.read_register_at(fifo_control_address(fifo)).await?;
+            let control = FIFOControlM::from_bytes(data);
+            let timestamp = !control.txen() && control.rxtsen();
+            fsizes[fifo as usize + 1] = control.fsize() + 1;
+            layout.object_sizes[fifo as usize + 1] = 8 + if timestamp { 4 } else { 0 } + control.plsize().bytes() as u16;
+        }
+        for (index, fsize) in fsizes.into_iter().enumerate() {
+            layout.starts[index + 1] = layout.starts[index] + fsize as u16 * layout.object_sizes[index];
+        }
+
+        if can_control.opmode() != OperationMode::Configuration {
+            self.ram_layout = Some(layout);
+        }
+        Ok(layout)
+    }
+
+    /// Set `FRESET` in the FIFO control register at `address` and wait for it to clear
+    pub(crate) async fn reset_fifo_at(&mut self, address: u16) -> Result<(), Error<SPI>> {
+        const RESET_ATTEMPTS: usize = 100;
+
+        self.write_register_byte(address + 1, FIFO_FRESET).await?;
+        for _ in 0..RESET_ATTEMPTS {
+            let control = self.read_register_at(address).await?;
+            if control[1] & FIFO_FRESET == 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::ControllerError("FIFO reset did not complete"))
+    }
+}
+
+/// Where the TEF, the TXQ and FIFOs 1 to 31 are in message RAM, in that order
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct RAMLayout {
+    /// Offset of each FIFO from the start of RAM, followed by the end of the last one
+    starts: [u16; 34],
+    object_sizes: [u16; 33],
+}
+impl RAMLayout {
+    /// Offsets taken by the TEF (0), the TXQ (1) or FIFO `index - 1`
+    fn range(&self, index: usize) -> Range<u16> {
+        self.starts[index]..self.starts[index + 1]
+    }
+
+    /// Offset of the first message object and object size of TX FIFO `fifo`, 0 for the TXQ
+    pub(crate) fn tx_fifo(&self, fifo: u8) -> (u16, u16) {
+        let index = if fifo == 0 { 1 } else { fifo as usize + 1 };
+        (self.starts[index], self.object_sizes[index])
+    }
+}
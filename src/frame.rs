@@ -48,16 +48,17 @@ impl Frame {
     pub fn sequence_number(&self) -> Option<u32> { self.sequence_number }
 
     pub(crate) fn as_components(&self) -> (TransmitMessageObjectHeader, &[u8]) {
-        let (sid, eid) = match self.id {
-            Id::Standard(id) => (id.as_raw(), 0),
-            Id::Extended(id) => ((id.as_raw() & 0x7FF) as u16, id.as_raw() >> 11),
+        let (sid, eid, extended) = match self.id {
+            Id::Standard(id) => (id.as_raw(), 0, false),
+            // SID holds the 11 most significant bits of an extended identifier
+            Id::Extended(id) => ((id.as_raw() >> 18) as u16, id.as_raw() & 0x3FFFF, true),
         };
         let is_fd_frame = self.dlc.bytes() > 8;
         let header = TransmitMessageObjectHeader::new()
             .with_sid(sid)
             .with_eid(eid)
             .with_seq(self.sequence_number.unwrap_or(0))
-            .with_ide(eid > 0)
+            .with_ide(extended)
             .with_fdf(is_fd_frame)
             .with_brs(is_fd_frame) // Always send FD frames at the data bitrate
             .with_dlc(self.dlc);
@@ -65,10 +66,10 @@ impl Frame {
     }
     pub(crate) fn from_rx_message(header: ReceiveMessageObjectHeader, data: [u8; 64]) -> Self {
         Self {
-            id: if header.eid() == 0 {
-                StandardId::new(header.sid()).unwrap().into()
+            id: if header.ide() {
+                ExtendedId::new(((header.sid() as u32) << 18) | header.eid()).unwrap().into()
             } else {
-                ExtendedId::new(header.eid() << 11 + header.sid()).unwrap().into()
+                StandardId::new(header.sid()).unwrap().into()
             },
             dlc: header.dlc(),
             data,
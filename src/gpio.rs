@@ -0,0 +1,183 @@
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin, PinState, StatefulOutputPin};
+use embedded_hal_async::spi::SpiDevice;
+use crate::config::TransceiverStandby;
+use crate::interrupt::InterruptPins;
+use crate::registers::IOControl;
+use crate::{Error, MCP25xxFD};
+
+/// Direction of a general purpose pin
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PinMode {
+    Input,
+    /// Push-pull output, driven to the given level when the pin is claimed
+    Output(PinState),
+}
+
+/// GPIO0 (`N = 0`) or GPIO1 (`N = 1`) borrowed as an `embedded_hal::digital` pin, which needs the
+/// SPI device to be blocking as well
+///
+/// The handle borrows the driver, so it is meant for handing the pin to code written against the
+/// digital traits for a while. Pins toggled between transmissions, e.g. a transceiver enable or a
+/// status LED, are better driven with [`MCP25xxFD::set_gpio`], which leaves the driver free.
+pub struct Gpio<'a, SPI, INT, const N: u8> {
+    driver: &'a mut MCP25xxFD<SPI, INT>,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Claim GPIO`N` as a general purpose pin and set its direction, or change the direction of
+    /// a pin claimed before
+    ///
+    /// Once claimed, the pin cannot be configured as an interrupt pin until the controller is reset.
+    pub async fn configure_gpio<const N: u8>(&mut self, mode: PinMode) -> Result<(), Error<SPI>> {
+        const { assert!(N < 2, "The controller only has GPIO0 and GPIO1") };
+        if N == 0 && self.transceiver_standby != TransceiverStandby::Disabled {
+            return Err(Error::ControllerError("GPIO0 is used for transceiver standby"));
+        }
+        if self.interrupt_pins & (1 << N) != 0 {
+            return Err(Error::ControllerError("GPIO is configured as an interrupt pin"));
+        }
+
+        let mut io_control: IOControl = self.read_register().await?;
+        configure_pin::<N>(&mut io_control, mode);
+        self.write_register(io_control).await?;
+        self.gpio_pins |= 1 << N;
+        Ok(())
+    }
+
+    /// Whether GPIO`N` has been claimed as a general purpose pin since the last reset
+    pub fn gpio_claimed(&self, n: u8) -> bool {
+        self.gpio_pins & (1 << n) != 0
+    }
+
+    /// Drive the output latch of GPIO`N`
+    pub async fn set_gpio<const N: u8>(&mut self, state: PinState) -> Result<(), Error<SPI>> {
+        self.check_gpio::<N>()?;
+        let mut io_control: IOControl = self.read_register().await?;
+        set_latch::<N>(&mut io_control, state);
+        self.write_register(io_control).await
+    }
+
+    /// Read the level present on GPIO`N`
+    pub async fn read_gpio<const N: u8>(&mut self) -> Result<PinState, Error<SPI>> {
+        self.check_gpio::<N>()?;
+        let io_control: IOControl = self.read_register().await?;
+        Ok(level::<N>(&io_control))
+    }
+
+    /// Read the level the output latch of GPIO`N` is set to
+    pub async fn read_gpio_latch<const N: u8>(&mut self) -> Result<PinState, Error<SPI>> {
+        self.check_gpio::<N>()?;
+        let io_control: IOControl = self.read_register().await?;
+        Ok(latch::<N>(&io_control))
+    }
+
+    /// Borrow GPIO`N`, claimed with [`configure_gpio`](Self::configure_gpio), as an
+    /// `embedded_hal::digital` pin
+    pub fn gpio<const N: u8>(&mut self) -> Result<Gpio<'_, SPI, INT, N>, Error<SPI>> {
+        self.check_gpio::<N>()?;
+        Ok(Gpio { driver: self })
+    }
+
+    fn check_gpio<const N: u8>(&self) -> Result<(), Error<SPI>> {
+        const { assert!(N < 2, "The controller only has GPIO0 and GPIO1") };
+        if N == 0 && self.transceiver_standby != TransceiverStandby::Disabled {
+            return Err(Error::ControllerError("GPIO0 is used for transceiver standby"));
+        }
+        if !self.gpio_claimed(N) {
+            return Err(Error::ControllerError("GPIO is not claimed as a general purpose pin"));
+        }
+        Ok(())
+    }
+}
+
+fn configure_pin<const N: u8>(io_control: &mut IOControl, mode: PinMode) {
+    let input = mode == PinMode::Input;
+    if let PinMode::Output(state) = mode {
+        set_latch::<N>(io_control, state);
+    }
+    match N {
+        0 => {
+            io_control.set_pm0(true);
+            io_control.set_tris0(input);
+        },
+        _ => {
+            io_control.set_pm1(true);
+            io_control.set_tris1(input);
+        },
+    }
+}
+
+fn set_latch<const N: u8>(io_control: &mut IOControl, state: PinState) {
+    match N {
+        0 => io_control.set_lat0(state.into()),
+        _ => io_control.set_lat1(state.into()),
+    }
+}
+
+fn latch<const N: u8>(io_control: &IOControl) -> PinState {
+    match N {
+        0 => io_control.lat0().into(),
+        _ => io_control.lat1().into(),
+    }
+}
+
+fn level<const N: u8>(io_control: &IOControl) -> PinState {
+    match N {
+        0 => io_control.gpio0().into(),
+        _ => io_control.gpio1().into(),
+    }
+}
+
+impl<SPI: SpiDevice> embedded_hal::digital::Error for Error<SPI> {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
+impl<SPI: SpiDevice, INT, const N: u8> ErrorType for Gpio<'_, SPI, INT, N> {
+    type Error = Error<SPI>;
+}
+
+impl<SPI, INT, const N: u8> OutputPin for Gpio<'_, SPI, INT, N>
+where
+    SPI: SpiDevice + embedded_hal::spi::SpiDevice,
+{
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::Low)
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::High)
+    }
+    fn set_state(&mut self, state: PinState) -> Result<(), Self::Error> {
+        let mut io_control: IOControl = self.driver.read_register_blocking()?;
+        set_latch::<N>(&mut io_control, state);
+        self.driver.write_register_blocking(io_control)
+    }
+}
+
+impl<SPI, INT, const N: u8> StatefulOutputPin for Gpio<'_, SPI, INT, N>
+where
+    SPI: SpiDevice + embedded_hal::spi::SpiDevice,
+{
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        let io_control: IOControl = self.driver.read_register_blocking()?;
+        Ok(latch::<N>(&io_control) == PinState::High)
+    }
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_set_high()?)
+    }
+}
+
+impl<SPI, INT, const N: u8> InputPin for Gpio<'_, SPI, INT, N>
+where
+    SPI: SpiDevice + embedded_hal::spi::SpiDevice,
+{
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        let io_control: IOControl = self.driver.read_register_blocking()?;
+        Ok(level::<N>(&io_control) == PinState::High)
+    }
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
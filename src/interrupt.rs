@@ -0,0 +1,167 @@
+use core::future::Future;
+use embedded_hal::digital::{Error as _, ErrorKind};
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+use crate::registers::*;
+use crate::{Error, Instruction, MCP25xxFD};
+
+/// Interrupt line(s) the driver waits on instead of polling the controller
+///
+/// The controller's interrupt outputs are active low and stay asserted while an enabled
+/// interrupt flag is pending, so waiting returns immediately if the condition is already met.
+pub trait InterruptPins {
+    /// Whether a pin is actually connected. Without one, the driver never waits.
+    const CONNECTED: bool;
+
+    /// Wait until the receive interrupt may be pending
+    fn wait_for_receive(&mut self) -> impl Future<Output = Result<(), ErrorKind>>;
+
+    /// Wait until the transmit interrupt may be pending
+    fn wait_for_transmit(&mut self) -> impl Future<Output = Result<(), ErrorKind>>;
+}
+
+/// No interrupt pin connected, the driver polls the controller over SPI
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoInterruptPin;
+
+impl InterruptPins for NoInterruptPin {
+    const CONNECTED: bool = false;
+
+    async fn wait_for_receive(&mut self) -> Result<(), ErrorKind> {
+        Ok(())
+    }
+    async fn wait_for_transmit(&mut self) -> Result<(), ErrorKind> {
+        Ok(())
+    }
+}
+
+/// A single interrupt pin: the shared INT pin, asserted for any enabled interrupt, or INT0 or INT1
+/// on its own as held by each half of a [split](MCP25xxFD::split) driver
+pub struct InterruptPin<P>(pub P);
+
+impl<P: Wait> InterruptPins for InterruptPin<P> {
+    const CONNECTED: bool = true;
+
+    async fn wait_for_receive(&mut self) -> Result<(), ErrorKind> {
+        self.0.wait_for_low().await.map_err(|e| e.kind())
+    }
+    async fn wait_for_transmit(&mut self) -> Result<(), ErrorKind> {
+        self.0.wait_for_low().await.map_err(|e| e.kind())
+    }
+}
+
+/// Dedicated interrupt pins: INT1 (GPIO1) for receive and INT0 (GPIO0) for transmit
+///
+/// Both GPIOs have to be switched to interrupt mode through [`Config::rx_interrupt_pin`] and
+/// [`Config::tx_interrupt_pin`]. Waiting on separate pins means pending receive interrupts don't
+/// hold up a transmit waiting for FIFO space.
+///
+/// [`Config::rx_interrupt_pin`]: crate::config::Config::rx_interrupt_pin
+/// [`Config::tx_interrupt_pin`]: crate::config::Config::tx_interrupt_pin
+pub struct SeparateInterruptPins<RX, TX> {
+    /// INT1, asserted while the receive interrupt flag is set
+    pub rx: RX,
+    /// INT0, asserted while the transmit interrupt flag is set
+    pub tx: TX,
+}
+
+impl<RX: Wait, TX: Wait> InterruptPins for SeparateInterruptPins<RX, TX> {
+    const CONNECTED: bool = true;
+
+    async fn wait_for_receive(&mut self) -> Result<(), ErrorKind> {
+        self.rx.wait_for_low().await.map_err(|e| e.kind())
+    }
+    async fn wait_for_transmit(&mut self) -> Result<(), ErrorKind> {
+        self.tx.wait_for_low().await.map_err(|e| e.kind())
+    }
+}
+
+// Flag bits of CiINT
+pub(crate) const INTERRUPT_TBCIF: u16 = 1 << 2;
+pub(crate) const INTERRUPT_MODIF: u16 = 1 << 3;
+pub(crate) const INTERRUPT_SERRIF: u16 = 1 << 12;
+pub(crate) const INTERRUPT_CERRIF: u16 = 1 << 13;
+pub(crate) const INTERRUPT_WAKIF: u16 = 1 << 14;
+pub(crate) const INTERRUPT_IVMIF: u16 = 1 << 15;
+
+/// Highest priority interrupt pending on the controller, as reported by `CiVEC`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InterruptEvent {
+    /// RX FIFO `fifo` has a frame, matched by filter `filter_hit`
+    Receive { fifo: u8, filter_hit: u8 },
+    /// TX FIFO `fifo` (0 for the TXQ) raised its interrupt
+    Transmit { fifo: u8 },
+    /// The Transmit Event FIFO has an event
+    TransmitEventFIFO,
+    /// CAN bus error
+    Error,
+    /// Bus activity woke the controller up
+    WakeUp,
+    /// An RX FIFO overflowed, cleared by
+    /// [`check_receive_overflows`](MCP25xxFD::check_receive_overflows)
+    ReceiveFIFOOverflow,
+    /// Illegal FIFO address presented to the system
+    AddressError,
+    /// Message Assembly Buffer underflow (TX) or overflow (RX)
+    RXTXMABUnderflowOverflow,
+    /// Time Base Counter overflow
+    TBCOverflow,
+    /// The operation mode changed
+    OperationModeChange,
+    /// Invalid message received
+    InvalidMessage,
+    /// A TX FIFO exhausted its transmission attempts, cleared by
+    /// [`take_attempts_exhausted`](MCP25xxFD::take_attempts_exhausted)
+    TransmitAttempt,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Read `CiVEC` and report the highest priority pending interrupt, or `None` if there is none
+    ///
+    /// Events that are only a flag in `CiINT` (bus error, wake-up, address error, MAB errors,
+    /// TBC overflow, mode change, invalid message) are cleared before returning. FIFO events clear
+    /// once the FIFO has been serviced, e.g. with [`receive_from_fifo`](Self::receive_from_fifo).
+    pub async fn service_interrupt(&mut self) -> Result<Option<InterruptEvent>, Error<SPI>> {
+        let code: InterruptCode = self.read_register().await?;
+        let (event, flag) = match code.icode() {
+            InterruptFlag::NoInterrupt => return Ok(None),
+            InterruptFlag::Error => (InterruptEvent::Error, INTERRUPT_CERRIF),
+            InterruptFlag::WakeUp => (InterruptEvent::WakeUp, INTERRUPT_WAKIF),
+            InterruptFlag::ReceiveFIFOOverflow => (InterruptEvent::ReceiveFIFOOverflow, 0),
+            InterruptFlag::AddressError => (InterruptEvent::AddressError, INTERRUPT_SERRIF),
+            InterruptFlag::RXTXMABUnderflowOverflow => (InterruptEvent::RXTXMABUnderflowOverflow, INTERRUPT_SERRIF),
+            InterruptFlag::TBCOverflow => (InterruptEvent::TBCOverflow, INTERRUPT_TBCIF),
+            InterruptFlag::OperationModeChange => (InterruptEvent::OperationModeChange, INTERRUPT_MODIF),
+            InterruptFlag::InvalidMessage => (InterruptEvent::InvalidMessage, INTERRUPT_IVMIF),
+            InterruptFlag::TransmitEventFIFO => (InterruptEvent::TransmitEventFIFO, 0),
+            InterruptFlag::TransmitAttempt => (InterruptEvent::TransmitAttempt, 0),
+            fifo if fifo == code.rxcode() => (InterruptEvent::Receive { fifo: fifo as u8, filter_hit: code.filhit() }, 0),
+            fifo => (InterruptEvent::Transmit { fifo: fifo as u8 }, 0),
+        };
+        if flag != 0 {
+            self.clear_interrupt_flags(flag).await?;
+        }
+        Ok(Some(event))
+    }
+
+    /// Clear flags in `CiINT` without a read-modify-write, so flags set in the meantime are kept
+    pub(crate) async fn clear_interrupt_flags(&mut self, flags: u16) -> Result<(), Error<SPI>> {
+        for (byte, mask) in flags.to_le_bytes().into_iter().enumerate() {
+            if mask != 0 {
+                // Writing 1 to a flag leaves it unchanged
+                self.write_register_byte(Interrupts::ADDRESS + byte as u16, !mask).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write only the enable half of `CiINT`, so flags set in the meantime aren't cleared
+    pub(crate) async fn write_interrupt_enables(&mut self, interrupts: Interrupts) -> Result<(), Error<SPI>> {
+        let enables = interrupts.serialize();
+        self.spi.transaction(&mut [
+            Operation::Write(&Instruction::Write.header(Interrupts::ADDRESS + 2)),
+            Operation::Write(&enables[2..]),
+        ]).await.map_err(Error::SPIError)
+    }
+}
@@ -3,35 +3,123 @@
 use core::fmt::{Debug, Display, Formatter};
 use embedded_can::Id;
 use embedded_hal_async::spi::{SpiDevice, Operation };
-use crate::config::{Config, FIFOConfig, FilterConfig, MaskConfig};
+use crate::config::{BitRateConfig, ClockOutput, Config, FIFOConfig, FilterConfig, MaskConfig, SPICRCConfig, TransceiverStandby};
+use crate::crc::SPICRCCounters;
 use crate::frame::Frame;
+use crate::interrupt::{InterruptPins, NoInterruptPin, INTERRUPT_CERRIF};
+use crate::overflow::ReceiveOverflows;
+use crate::ecc::{ECCCounters, RAMLayout};
+use crate::power::SleepState;
+use crate::transmit::AbortRecord;
 use crate::registers::*;
 
 /// Register bitfields
 pub mod registers;
 pub mod config;
 pub mod frame;
+pub mod gpio;
+pub mod interrupt;
+pub mod power;
+pub mod ecc;
+pub mod crc;
+pub mod overflow;
+pub mod transmit;
+pub mod monitor;
+pub mod autobaud;
+pub mod self_test;
+pub mod dump;
+pub mod split;
 
 const RAM_START: u16 = 0x400;
 const RAM_SIZE: u16 = 2048;
 
+// TXEN in the first byte of CiFIFOCONm
+const FIFO_TXEN: u8 = 1 << 7;
+// TFNRFNIF in the first byte of CiFIFOSTAm
+const FIFO_NOT_EMPTY: u8 = 1 << 0;
+
+/// Address of `CiFIFOCONm`, with FIFO 0 being the TXQ. `CiFIFOSTAm` and `CiFIFOUAm` follow it.
+pub(crate) const fn fifo_control_address(fifo: u8) -> u16 {
+    0x050 + 12 * fifo as u16
+}
+pub(crate) const fn fifo_status_address(fifo: u8) -> u16 {
+    fifo_control_address(fifo) + 4
+}
+pub(crate) const fn fifo_user_address(fifo: u8) -> u16 {
+    fifo_control_address(fifo) + 8
+}
+
 /// Either a MCP2517, MCP2518 or MCP251863 CAN-FD controller
-pub struct MCP25xxFD<SPI> {
+///
+/// With an interrupt pin connected (see [`with_interrupt_pins`](Self::with_interrupt_pins)),
+/// [`receive`](Self::receive) and [`transmit`](Self::transmit) wait on the pin instead of
+/// returning straight away when there is nothing to receive or no room to transmit.
+pub struct MCP25xxFD<SPI, INT = NoInterruptPin> {
     spi: SPI,
+    int: INT,
+    /// Bitmask of GPIO pins claimed as general purpose pins
+    gpio_pins: u8,
+    /// Bitmask of GPIO pins configured as INT0/INT1
+    interrupt_pins: u8,
+    transceiver_standby: TransceiverStandby,
+    sleep_state: Option<SleepState>,
+    ecc_counters: ECCCounters,
+    crc_counters: SPICRCCounters,
+    /// Retries for writes rejected because of a CRC error
+    crc_write_retries: u8,
+    rx_overflows: ReceiveOverflows,
+    /// Sequence number given to the next tracked transmission
+    tx_sequence: u32,
+    /// Last abort that reset each TX FIFO, to tell aborted tracked frames from sent ones
+    tx_aborts: [AbortRecord; 32],
+    /// Bitmask of RX FIFOs whose interrupt fired and that are read until empty
+    rx_draining: u32,
+    /// Message RAM layout, kept while the controller stays out of Configuration mode
+    ram_layout: Option<RAMLayout>,
 }
 
 impl<SPI: SpiDevice> MCP25xxFD<SPI> {
     pub fn new(spi: SPI) -> Self {
+        Self::with_interrupt_pins(spi, NoInterruptPin)
+    }
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    pub fn with_interrupt_pins(spi: SPI, int: INT) -> Self {
         Self {
             spi,
+            int,
+            gpio_pins: 0,
+            interrupt_pins: 0,
+            transceiver_standby: TransceiverStandby::Disabled,
+            sleep_state: None,
+            ecc_counters: ECCCounters::default(),
+            crc_counters: SPICRCCounters::default(),
+            crc_write_retries: SPICRCConfig::default().write_retries,
+            rx_overflows: ReceiveOverflows::default(),
+            tx_sequence: 0,
+            tx_aborts: [AbortRecord::default(); 32],
+            rx_draining: 0,
+            ram_layout: None,
         }
     }
 
     pub async fn reset_and_apply_config(&mut self, config: &Config) -> Result<(), Error<SPI>> {
         self.reset().await?;
 
+        let mut oscillator: OscillatorControl = self.read_register().await?;
+        oscillator.set_pllen(config.clock.pll_enabled());
+        oscillator.set_sclkdiv(config.clock.divisor());
+        if let ClockOutput::Clock(divisor) = config.pins.clock_output {
+            oscillator.set_clkodiv(divisor);
+        }
+        self.write_register(oscillator).await?;
+        self.wait_for_clock(config.clock.pll_enabled()).await?;
+
         let mut ecc_register: ECCControl = self.read_register().await?;
         ecc_register.set_eccen(config.ecc_enabled);
+        ecc_register.set_secie(config.ecc_enabled);
+        ecc_register.set_dedie(config.ecc_enabled);
         self.write_register(ecc_register).await?;
 
         self.initialize_ram(0xFF).await?;
@@ -40,36 +128,72 @@ impl<SPI: SpiDevice> MCP25xxFD<SPI> {
         can_config.set_isocrcen(config.iso_crc_enabled);
         can_config.set_stef(config.tx_event_fifo_enabled);
         can_config.set_txqen(config.txq_enabled);
-        can_config.set_rtxat(config.restrict_retx_attempts);
+        // TXAT of each FIFO decides, see `RetransmissionPolicy`
+        can_config.set_rtxat(true);
+        can_config.set_txbws(config.bandwidth_sharing.bits());
         self.write_register(can_config).await?;
 
-        let bitrate_config = config.bit_rate.get_config(&config.clock).expect("Invalid bit rate pair for system clock");
+        let mut txq_control: TransmitQueueControl = self.read_register().await?;
+        txq_control.set_txat(config.txq_retransmission.attempts());
+        self.write_register(txq_control).await?;
 
-        let mut nominal_bit_time_config = NominalBitTimeConfig::new();
-        nominal_bit_time_config.set_brp(bitrate_config.arbitration_brp);
-        nominal_bit_time_config.set_tseg1(bitrate_config.arbitration_tseg1);
-        nominal_bit_time_config.set_tseg2(bitrate_config.arbitration_tseg2);
-        nominal_bit_time_config.set_sjw(bitrate_config.arbitration_sjw);
-        self.write_register(nominal_bit_time_config).await?;
+        let bitrate_config = config.bit_rate_config()
+            .ok_or(Error::ControllerError("Invalid bit timing for system clock"))?;
 
-        let mut data_bit_time_config = DataBitTimeConfig::new();
-        data_bit_time_config.set_brp(bitrate_config.data_brp);
-        data_bit_time_config.set_tseg1(bitrate_config.data_tseg1);
-        data_bit_time_config.set_tseg2(bitrate_config.data_tseg2);
-        data_bit_time_config.set_sjw(bitrate_config.data_sjw);
-        self.write_register(data_bit_time_config).await?;
+        self.write_bit_rate_config(&bitrate_config).await?;
 
         let mut tx_delay_compensation: TransmitterDelayCompensation = self.read_register().await?;
-        tx_delay_compensation.set_tdcmod(bitrate_config.tdc_mode);
-        tx_delay_compensation.set_tdco(bitrate_config.tdc_offset);
-        tx_delay_compensation.set_tdcv(bitrate_config.tdc_value);
+        tx_delay_compensation.set_edgflten(config.edge_filter_enabled);
         self.write_register(tx_delay_compensation).await?;
 
+        let mut io_control: IOControl = self.read_register().await?;
+        io_control.set_sof(config.pins.clock_output == ClockOutput::StartOfFrame);
+        io_control.set_txcanod(config.pins.txcan_open_drain);
+        io_control.set_intod(config.pins.interrupt_open_drain);
+        if config.transceiver_standby != TransceiverStandby::Disabled {
+            // GPIO0 output, low (transceiver active) until Sleep mode is requested
+            io_control.set_pm0(true);
+            io_control.set_tris0(false);
+            io_control.set_lat0(false);
+            io_control.set_xstbyen(config.transceiver_standby == TransceiverStandby::Automatic);
+            self.gpio_pins |= 1 << 0;
+        }
+        if config.tx_interrupt_pin {
+            if config.transceiver_standby != TransceiverStandby::Disabled {
+                return Err(Error::ControllerError("GPIO0 is used for transceiver standby"));
+            }
+            io_control.set_pm0(false);
+            self.interrupt_pins |= 1 << 0;
+        }
+        if config.rx_interrupt_pin {
+            io_control.set_pm1(false);
+            self.interrupt_pins |= 1 << 1;
+        }
+        self.write_register(io_control).await?;
+        self.transceiver_standby = config.transceiver_standby;
+
+        let mut crc_status: CRCStatus = self.read_register().await?;
+        crc_status.set_crcerrie(config.spi_crc.crc_error);
+        crc_status.set_ferrie(config.spi_crc.format_error);
+        self.write_register(crc_status).await?;
+        self.crc_write_retries = config.spi_crc.write_retries;
+
         // Setup interrupts
+        let interrupt_enables = &config.interrupts;
         let mut interrupt_config: Interrupts = self.read_register().await?;
-        interrupt_config.set_txie(false);
-        interrupt_config.set_rxie(true);
-        interrupt_config.set_cerrie(true);
+        interrupt_config.set_rxie(interrupt_enables.receive);
+        interrupt_config.set_txie(interrupt_enables.transmit);
+        interrupt_config.set_cerrie(interrupt_enables.bus_error);
+        interrupt_config.set_tefie(interrupt_enables.transmit_event_fifo);
+        interrupt_config.set_eccie(interrupt_enables.ecc);
+        interrupt_config.set_spicrcie(interrupt_enables.spi_crc);
+        interrupt_config.set_txatie(interrupt_enables.transmit_attempt);
+        interrupt_config.set_rxovie(interrupt_enables.receive_overflow);
+        interrupt_config.set_serrie(interrupt_enables.system_error);
+        interrupt_config.set_wakeie(interrupt_enables.wake_up);
+        interrupt_config.set_ivmie(interrupt_enables.invalid_message);
+        interrupt_config.set_tbcie(interrupt_enables.time_base_counter);
+        interrupt_config.set_modie(interrupt_enables.mode_change);
         self.write_register(interrupt_config).await?;
 
         Ok(())
@@ -80,12 +204,14 @@ impl<SPI: SpiDevice> MCP25xxFD<SPI> {
         fifo_control.contents.set_fsize(fifo.size - 1); // FSIZE of 0 is 1 message deep
         fifo_control.contents.set_plsize(fifo.payload_size);
         fifo_control.contents.set_txen(fifo.transmit);
-        fifo_control.contents.set_txat(fifo.tx_attempts);
+        fifo_control.contents.set_txat(fifo.retransmission.attempts());
         fifo_control.contents.set_txpri(fifo.priority);
         fifo_control.contents.set_freset(true);
-        if !fifo.transmit {
-            fifo_control.contents.set_tfnrfnie(true); // Interrupt for RX FIFO not empty
-        }
+        fifo_control.contents.set_tfnrfnie(fifo.interrupts.not_full_not_empty);
+        fifo_control.contents.set_tfhrfhie(fifo.interrupts.half);
+        fifo_control.contents.set_tferffie(fifo.interrupts.empty_full);
+        fifo_control.contents.set_rxovie(!fifo.transmit && fifo.interrupts.overflow);
+        fifo_control.contents.set_txatie(fifo.transmit && fifo.interrupts.attempts_exhausted);
         self.write_register(fifo_control).await?;
         Ok(())
     }
@@ -99,8 +225,8 @@ impl<SPI: SpiDevice> MCP25xxFD<SPI> {
                 filter_object.contents.set_sid(id.as_raw());
             },
             Id::Extended(id) => {
-                let sid_component = id.as_raw() & 0x7FF;
-                let eid_component = id.as_raw() >> 11;
+                let sid_component = id.as_raw() >> 18;
+                let eid_component = id.as_raw() & 0x3FFFF;
                 filter_object.contents.set_sid(sid_component as u16);
                 filter_object.contents.set_eid(eid_component);
             },
@@ -115,8 +241,8 @@ impl<SPI: SpiDevice> MCP25xxFD<SPI> {
                 mask_config.contents.set_msid(id.as_raw());
             },
             Id::Extended(id) => {
-                let sid_component = id.as_raw() & 0x7FF;
-                let eid_component = id.as_raw() >> 11;
+                let sid_component = id.as_raw() >> 18;
+                let eid_component = id.as_raw() & 0x3FFFF;
                 mask_config.contents.set_msid(sid_component as u16);
                 mask_config.contents.set_meid(eid_component);
             },
@@ -130,27 +256,147 @@ impl<SPI: SpiDevice> MCP25xxFD<SPI> {
         Ok(())
     }
 
+    /// Wait for the oscillator (and optionally the PLL) to report ready after a clock change
+    ///
+    /// There is no time base to wait with, so the number of polls is sized to cover 3 ms, enough
+    /// for a crystal to start and the PLL to lock, at the fastest SPI clock the controller
+    /// supports. Slower SPI clocks only make the wait longer.
+    async fn wait_for_clock(&mut self, pll: bool) -> Result<(), Error<SPI>> {
+        const CLOCK_READY_TIMEOUT_NS: u32 = 3_000_000;
+        // 2 instruction and 4 data bytes at 20 MHz
+        const REGISTER_READ_NS: u32 = 6 * 8 * 50;
+        const CLOCK_READY_ATTEMPTS: u32 = CLOCK_READY_TIMEOUT_NS / REGISTER_READ_NS;
+
+        for _ in 0..CLOCK_READY_ATTEMPTS {
+            let oscillator: OscillatorControl = self.read_register().await?;
+            if oscillator.oscrdy() && (!pll || oscillator.pllrdy()) {
+                return Ok(());
+            }
+        }
+        Err(Error::ControllerError("Oscillator not ready"))
+    }
+
+    /// Write the bit timing and delay compensation registers, which requires Configuration mode
+    pub(crate) async fn write_bit_rate_config(&mut self, bitrate_config: &BitRateConfig) -> Result<(), Error<SPI>> {
+        self.write_register(bitrate_config.nominal_bit_time_config()).await?;
+        self.write_register(bitrate_config.data_bit_time_config()).await?;
+
+        let mut tx_delay_compensation: TransmitterDelayCompensation = self.read_register().await?;
+        tx_delay_compensation.set_tdcmod(bitrate_config.tdc_mode);
+        tx_delay_compensation.set_tdco(bitrate_config.tdc_offset as u8 & 0x7F);
+        tx_delay_compensation.set_tdcv(bitrate_config.tdc_value);
+        self.write_register(tx_delay_compensation).await
+    }
+
+    /// Clear the error flags in `CiBDIAG1`, leaving the error-free message counter running
+    pub(crate) async fn clear_bus_error_flags(&mut self) -> Result<(), Error<SPI>> {
+        self.write_register_byte(BusDiagnostic1::ADDRESS + 2, 0).await?;
+        self.write_register_byte(BusDiagnostic1::ADDRESS + 3, 0).await
+    }
+
+    /// Read back the bit timing registers currently programmed into the controller
+    ///
+    /// Use [`BitRateConfig::decode`] to turn the result into bit rates and sample points.
+    pub async fn read_bit_rate_config(&mut self) -> Result<BitRateConfig, Error<SPI>> {
+        let nominal: NominalBitTimeConfig = self.read_register().await?;
+        let data: DataBitTimeConfig = self.read_register().await?;
+        let tdc: TransmitterDelayCompensation = self.read_register().await?;
+        Ok(BitRateConfig::from_registers(nominal, data, tdc))
+    }
+
     /// Request the controller transition to the specified mode
+    ///
+    /// With [`TransceiverStandby::Manual`], the transceiver is put in standby before entering
+    /// Sleep mode and woken up when any other mode is requested.
     pub async fn set_mode(&mut self, mode: OperationMode) -> Result<(), Error<SPI>> {
+        let manual_standby = self.transceiver_standby == TransceiverStandby::Manual;
+        if manual_standby && mode == OperationMode::Sleep {
+            self.set_transceiver_standby(true).await?;
+        }
+
+        self.ram_layout = None;
         let mut can_config: CANControl = self.read_register().await?;
         can_config.set_reqop(mode);
-        self.write_register(can_config).await
+        self.write_register(can_config).await?;
+
+        if manual_standby && mode != OperationMode::Sleep {
+            self.set_transceiver_standby(false).await?;
+        }
+        Ok(())
+    }
+
+    /// Switch to Configuration mode and wait until the controller is in it, returning the mode it
+    /// was in before so that it can be restored afterwards
+    ///
+    /// Entering Configuration mode waits for any frame on the bus to complete.
+    pub(crate) async fn enter_configuration_mode(&mut self) -> Result<OperationMode, Error<SPI>> {
+        let can_control: CANControl = self.read_register().await?;
+        let mode = can_control.opmode();
+        if mode != OperationMode::Configuration {
+            self.set_mode(OperationMode::Configuration).await?;
+            self.wait_for_mode(OperationMode::Configuration).await?;
+        }
+        Ok(mode)
+    }
+
+    pub(crate) async fn wait_for_mode(&mut self, mode: OperationMode) -> Result<(), Error<SPI>> {
+        const MODE_CHANGE_ATTEMPTS: usize = 1000;
+
+        for _ in 0..MODE_CHANGE_ATTEMPTS {
+            let can_control: CANControl = self.read_register().await?;
+            if can_control.opmode() == mode {
+                return Ok(());
+            }
+        }
+        Err(Error::ControllerError("Operation mode change did not complete"))
+    }
+
+    /// Drive the transceiver standby pin, only available with [`TransceiverStandby::Manual`]
+    pub async fn set_transceiver_standby(&mut self, standby: bool) -> Result<(), Error<SPI>> {
+        if self.transceiver_standby != TransceiverStandby::Manual {
+            return Err(Error::ControllerError("Transceiver standby is not under manual control"));
+        }
+        let mut io_control: IOControl = self.read_register().await?;
+        io_control.set_lat0(standby);
+        self.write_register(io_control).await
     }
 
     /// Resets the controller and places it back into Configuration Mode
     pub async fn reset(&mut self) -> Result<(), Error<SPI>> {
         let tx = Instruction::Reset.header(0x00);
         self.spi.write(&tx).await.map_err(Error::SPIError)?;
+        self.gpio_pins = 0;
+        self.interrupt_pins = 0;
+        self.transceiver_standby = TransceiverStandby::Disabled;
+        self.sleep_state = None;
+        self.rx_draining = 0;
+        self.ram_layout = None;
         Ok(())
     }
 
     /// Read a single register
     pub async fn read_register<R: Register>(&mut self) -> Result<R, Error<SPI>> {
-        let tx = Instruction::Read.header(R::ADDRESS);
+        let data = self.read_register_at(R::ADDRESS).await?;
+        Ok(R::parse(&data))
+    }
+
+    /// Read the raw contents of the register at `address`, for registers only known at runtime
+    pub async fn read_register_at(&mut self, address: u16) -> Result<[u8; 4], Error<SPI>> {
+        let tx = Instruction::Read.header(address);
         let mut rx = [0u8; 6];
         self.spi.transfer(&mut rx, &tx).await.map_err(Error::SPIError)?;
 
-        Ok(R::parse(&rx[2..]))
+        Ok(rx[2..].try_into().unwrap())
+    }
+
+    /// Write raw contents to the register at `address`, for registers only known at runtime
+    pub async fn write_register_at(&mut self, address: u16, data: [u8; 4]) -> Result<(), Error<SPI>> {
+        self.spi.transaction(&mut [
+            Operation::Write(&Instruction::Write.header(address)),
+            Operation::Write(&data),
+        ]).await.map_err(Error::SPIError)?;
+
+        Ok(())
     }
 
     /// Write a single register
@@ -209,31 +455,66 @@ impl<SPI: SpiDevice> MCP25xxFD<SPI> {
         Ok(())
     }
 
+    /// Queue a frame in TX FIFO `M`, or the TXQ for `M` = 0, and request its transmission
+    ///
+    /// If the FIFO is full, this waits for room when an interrupt pin is connected and fails
+    /// otherwise. Waiting needs the FIFO's not full interrupt and the transmit interrupt enabled,
+    /// and fails if the pin asserted for another interrupt.
     pub async fn transmit<const M: u8>(&mut self, frame: &Frame) -> Result<(), Error<SPI>> {
+        let (header, data) = frame.as_components();
+        self.queue_message::<M>(header, data).await
+    }
+
+    /// Write a message to TX FIFO `M` and request its transmission
+    async fn queue_message<const M: u8>(&mut self, header: TransmitMessageObjectHeader, data: &[u8]) -> Result<(), Error<SPI>> {
+        let tx_addr = self.next_message_object::<M>().await?;
+        self.queue_message_at::<M>(tx_addr, header, data).await
+    }
+
+    /// Wait for room in TX FIFO `M`, returning the RAM address of the message object to queue the
+    /// next message in
+    pub(crate) async fn next_message_object<const M: u8>(&mut self) -> Result<u16, Error<SPI>> {
         // Check FIFO availability
         let tx_status: FIFOStatus<M> = self.read_register().await?;
         if !tx_status.contents.tfnrfnif() {
-            return Err(Error::ControllerError("No room in TX FIFO!"));
+            if !INT::CONNECTED {
+                return Err(Error::ControllerError("No room in TX FIFO!"));
+            }
+            self.wait_for_tx_fifo::<M>().await?;
         }
 
-        let (header, data) = frame.as_components();
-
-        let tx_addr = self.read_register::<FIFOUserAddress<M>>().await?
+        Ok(self.read_register::<FIFOUserAddress<M>>().await?
             .contents
-            .fifoua() as u16;
+            .fifoua() as u16)
+    }
 
+    /// Write a message to the message object at `tx_addr`, given by
+    /// [`next_message_object`](Self::next_message_object), and request its transmission
+    pub(crate) async fn queue_message_at<const M: u8>(&mut self, tx_addr: u16, header: TransmitMessageObjectHeader, data: &[u8]) -> Result<(), Error<SPI>> {
         self.write_bytes(tx_addr, &header.into_bytes()).await?;
         self.write_bytes(tx_addr + size_of::<TransmitMessageObjectHeader>() as u16, data).await?;
 
         let mut tx_control: FIFOControl<M> = self.read_register().await?;
         tx_control.contents.set_uinc(true); // Increment FIFO pointer
         tx_control.contents.set_txreq(true); // Request send
-        self.write_register(tx_control).await?;
+        self.write_register(tx_control).await
+    }
 
+    /// Wait on the interrupt pin until TX FIFO `M` is not full
+    ///
+    /// The pin only asserts for this if the FIFO's not full interrupt and `TXIE` are enabled.
+    async fn wait_for_tx_fifo<const M: u8>(&mut self) -> Result<(), Error<SPI>> {
+        self.int.wait_for_transmit().await.map_err(Error::InterruptPinError)?;
+        let tx_status: FIFOStatus<M> = self.read_register().await?;
+        if !tx_status.contents.tfnrfnif() {
+            return Err(Error::ControllerError("No room in TX FIFO!"));
+        }
         Ok(())
     }
 
     async fn get_rx_frame<const M: u8>(&mut self) -> Result<Option<(u8, Frame)>, Error<SPI>> {
+        self.check_fifo_overflow::<M>().await?;
+
         // Get the RAM address of the message
         let rx_addr = self.read_register::<FIFOUserAddress<M>>().await?.contents.fifoua() as u16;
 
@@ -249,68 +530,121 @@ impl<SPI: SpiDevice> MCP25xxFD<SPI> {
         Ok(Some((M, frame)))
     }
 
-    async fn receive_first_fifo(&mut self, fifo: u8, rx_interrupts: &ReceiveInterruptStatus) -> Result<Option<(u8, Frame)>, Error<SPI>> {
+    /// Receive the next frame from RX FIFO `fifo` (1 to 31), which must not be empty
+    ///
+    /// An overflow of the FIFO is recorded in [`receive_overflows`](Self::receive_overflows).
+    pub async fn receive_from_fifo(&mut self, fifo: u8) -> Result<Option<(u8, Frame)>, Error<SPI>> {
         match fifo {
-             1 if rx_interrupts.fifo1() =>  self.get_rx_frame::<1>().await,
-             2 if rx_interrupts.fifo2() =>  self.get_rx_frame::<2>().await,
-             3 if rx_interrupts.fifo3() =>  self.get_rx_frame::<3>().await,
-             4 if rx_interrupts.fifo4() =>  self.get_rx_frame::<4>().await,
-             5 if rx_interrupts.fifo5() =>  self.get_rx_frame::<5>().await,
-             6 if rx_interrupts.fifo6() =>  self.get_rx_frame::<6>().await,
-             7 if rx_interrupts.fifo7() =>  self.get_rx_frame::<7>().await,
-             8 if rx_interrupts.fifo8() =>  self.get_rx_frame::<8>().await,
-             9 if rx_interrupts.fifo9() =>  self.get_rx_frame::<9>().await,
-            10 if rx_interrupts.fifo10() => self.get_rx_frame::<10>().await,
-            11 if rx_interrupts.fifo11() => self.get_rx_frame::<11>().await,
-            12 if rx_interrupts.fifo12() => self.get_rx_frame::<12>().await,
-            13 if rx_interrupts.fifo13() => self.get_rx_frame::<13>().await,
-            14 if rx_interrupts.fifo14() => self.get_rx_frame::<14>().await,
-            15 if rx_interrupts.fifo15() => self.get_rx_frame::<15>().await,
-            16 if rx_interrupts.fifo16() => self.get_rx_frame::<16>().await,
-            17 if rx_interrupts.fifo17() => self.get_rx_frame::<17>().await,
-            18 if rx_interrupts.fifo18() => self.get_rx_frame::<18>().await,
-            19 if rx_interrupts.fifo19() => self.get_rx_frame::<19>().await,
-            20 if rx_interrupts.fifo20() => self.get_rx_frame::<20>().await,
-            21 if rx_interrupts.fifo21() => self.get_rx_frame::<21>().await,
-            22 if rx_interrupts.fifo22() => self.get_rx_frame::<22>().await,
-            23 if rx_interrupts.fifo23() => self.get_rx_frame::<23>().await,
-            24 if rx_interrupts.fifo24() => self.get_rx_frame::<24>().await,
-            25 if rx_interrupts.fifo25() => self.get_rx_frame::<25>().await,
-            26 if rx_interrupts.fifo26() => self.get_rx_frame::<26>().await,
-            27 if rx_interrupts.fifo27() => self.get_rx_frame::<27>().await,
-            28 if rx_interrupts.fifo28() => self.get_rx_frame::<28>().await,
-            29 if rx_interrupts.fifo29() => self.get_rx_frame::<29>().await,
-            30 if rx_interrupts.fifo30() => self.get_rx_frame::<30>().await,
-            31 if rx_interrupts.fifo31() => self.get_rx_frame::<31>().await,
+             1 =>  self.get_rx_frame::<1>().await,
+             2 =>  self.get_rx_frame::<2>().await,
+             3 =>  self.get_rx_frame::<3>().await,
+             4 =>  self.get_rx_frame::<4>().await,
+             5 =>  self.get_rx_frame::<5>().await,
+             6 =>  self.get_rx_frame::<6>().await,
+             7 =>  self.get_rx_frame::<7>().await,
+             8 =>  self.get_rx_frame::<8>().await,
+             9 =>  self.get_rx_frame::<9>().await,
+            10 => self.get_rx_frame::<10>().await,
+            11 => self.get_rx_frame::<11>().await,
+            12 => self.get_rx_frame::<12>().await,
+            13 => self.get_rx_frame::<13>().await,
+            14 => self.get_rx_frame::<14>().await,
+            15 => self.get_rx_frame::<15>().await,
+            16 => self.get_rx_frame::<16>().await,
+            17 => self.get_rx_frame::<17>().await,
+            18 => self.get_rx_frame::<18>().await,
+            19 => self.get_rx_frame::<19>().await,
+            20 => self.get_rx_frame::<20>().await,
+            21 => self.get_rx_frame::<21>().await,
+            22 => self.get_rx_frame::<22>().await,
+            23 => self.get_rx_frame::<23>().await,
+            24 => self.get_rx_frame::<24>().await,
+            25 => self.get_rx_frame::<25>().await,
+            26 => self.get_rx_frame::<26>().await,
+            27 => self.get_rx_frame::<27>().await,
+            28 => self.get_rx_frame::<28>().await,
+            29 => self.get_rx_frame::<29>().await,
+            30 => self.get_rx_frame::<30>().await,
+            31 => self.get_rx_frame::<31>().await,
             _ => Ok(None),
         }
     }
 
+    /// Receive a frame from the lowest numbered RX FIFO holding one, or only from
+    /// `fifo_restriction` if given
+    ///
+    /// Without an interrupt pin, returns `Ok(None)` if no frame is waiting. With one, waits for
+    /// the pin to assert first. The pin is asserted by any enabled interrupt, so this still returns
+    /// `Ok(None)` when it was raised for something other than a frame here, e.g. a frame in
+    /// another FIFO or a pending transmit interrupt. That interrupt has to be serviced, see
+    /// [`service_interrupt`](Self::service_interrupt), or it keeps the pin asserted.
     pub async fn receive(&mut self, fifo_restriction: Option<u8>) -> Result<Option<(u8, Frame)>, Error<SPI>> {
-        let mut interrupts: Interrupts = self.read_register().await?;
+        let frame = self.poll_receive(fifo_restriction).await?;
+        if frame.is_some() || !INT::CONNECTED {
+            return Ok(frame);
+        }
+        self.int.wait_for_receive().await.map_err(Error::InterruptPinError)?;
+        self.poll_receive(fifo_restriction).await
+    }
+
+    async fn poll_receive(&mut self, fifo_restriction: Option<u8>) -> Result<Option<(u8, Frame)>, Error<SPI>> {
+        let interrupts: Interrupts = self.read_register().await?;
         if interrupts.cerrif() {
             // CAN Bus error
-            interrupts.set_cerrif(false);
-            self.write_register(interrupts).await?;
+            self.clear_interrupt_flags(INTERRUPT_CERRIF).await?;
             Err(Error::ControllerError("CAN Bus error!"))
         }
-        else if interrupts.rxif() {
-            let rx_interrupts: ReceiveInterruptStatus = self.read_register().await?;
+        else {
+            let pending = if interrupts.rxif() {
+                let rx_interrupts: ReceiveInterruptStatus = self.read_register().await?;
+                u32::from_le_bytes(rx_interrupts.serialize())
+            }
+            else {
+                0
+            };
 
-            let mut frame = None;
+            // A FIFO is read until empty once its interrupt fired, as the interrupt may only flag
+            // it half full or full and would not fire again for the frames left behind
             for i in 1..=31 {
-                if frame.is_none() && fifo_restriction.unwrap_or(i) == i {
-                    frame = self.receive_first_fifo(i, &rx_interrupts).await?;
+                if fifo_restriction.unwrap_or(i) != i || (pending | self.rx_draining) & (1 << i) == 0 {
+                    continue;
+                }
+                if pending & (1 << i) == 0 {
+                    let status = self.read_register_at(fifo_status_address(i)).await?;
+                    if status[0] & FIFO_NOT_EMPTY == 0 {
+                        self.rx_draining &= !(1 << i);
+                        continue;
+                    }
                 }
+                self.rx_draining |= 1 << i;
+                return self.receive_from_fifo(i).await;
             }
-            Ok(frame)
-        }
-        else {
             Ok(None)
         }
     }
 }
 
+impl<SPI: SpiDevice + embedded_hal::spi::SpiDevice, INT> MCP25xxFD<SPI, INT> {
+    /// Read a single register using the blocking SPI interface
+    pub fn read_register_blocking<R: Register>(&mut self) -> Result<R, Error<SPI>> {
+        let tx = Instruction::Read.header(R::ADDRESS);
+        let mut rx = [0u8; 6];
+        embedded_hal::spi::SpiDevice::transfer(&mut self.spi, &mut rx, &tx).map_err(Error::SPIError)?;
+
+        Ok(R::parse(&rx[2..]))
+    }
+
+    /// Write a single register using the blocking SPI interface
+    pub fn write_register_blocking<R: Register>(&mut self, register: R) -> Result<(), Error<SPI>> {
+        embedded_hal::spi::SpiDevice::transaction(&mut self.spi, &mut [
+            Operation::Write(&Instruction::Write.header(R::ADDRESS)),
+            Operation::Write(&R::serialize(register)),
+        ]).map_err(Error::SPIError)?;
+
+        Ok(())
+    }
+}
+
 /// SPI instructions supported by the CAN controller
 #[derive(Copy, Clone, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -344,12 +678,14 @@ impl Instruction {
 pub enum Error<SPI: SpiDevice> {
     SPIError(SPI::Error),
     ControllerError(&'static str),
+    InterruptPinError(embedded_hal::digital::ErrorKind),
 }
 impl<SPI: SpiDevice> Display for Error<SPI> {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Error::SPIError(err) => err.fmt(f),
             Error::ControllerError(msg) => f.write_str(msg),
+            Error::InterruptPinError(kind) => Display::fmt(kind, f),
         }
     }
 }
@@ -359,6 +695,7 @@ impl<SPI: SpiDevice> defmt::Format for Error<SPI> {
         defmt::write!(fmt, "{}", match self {
             Error::SPIError(_err) => "SPI error",
             Error::ControllerError(msg) => msg,
+            Error::InterruptPinError(_kind) => "Interrupt pin error",
         })
     }
 }
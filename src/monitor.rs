@@ -0,0 +1,166 @@
+use embedded_can::StandardId;
+use embedded_hal_async::spi::SpiDevice;
+use crate::config::{FIFOConfig, FilterConfig, MaskConfig};
+use crate::frame::Frame;
+use crate::interrupt::{InterruptPins, INTERRUPT_CERRIF, INTERRUPT_IVMIF};
+use crate::registers::*;
+use crate::transmit::BusError;
+use crate::{Error, MCP25xxFD, RAM_SIZE};
+
+/// RX FIFO the monitor receives every frame in
+const MONITOR_FIFO: u8 = 1;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MonitorConfig {
+    /// Depth of the RX FIFO catching every frame (1 to 32)
+    ///
+    /// Together with the TEF and TXQ, if enabled, the FIFO has to fit in the 2 KiB of message RAM.
+    pub fifo_size: u8,
+    /// Payload size of the RX FIFO. Frames with more data are received truncated to it, with
+    /// `DLCMM` set in `CiBDIAG1`.
+    pub payload_size: PayloadSize,
+}
+impl Default for MonitorConfig {
+    /// As deep as fits in RAM with 64 byte payloads, leaving room for a small TEF and TXQ
+    fn default() -> Self {
+        Self {
+            fifo_size: 24,
+            payload_size: PayloadSize::Bytes64,
+        }
+    }
+}
+
+/// Something seen on the bus by a [`Monitor`]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MonitorEvent {
+    Frame(Frame),
+    /// An error flagged in `CiBDIAG1`, the frame it happened in is not received
+    BusError(BusError),
+    /// A frame the controller couldn't make sense of
+    InvalidMessage,
+}
+
+/// Counts of what a [`Monitor`] has seen since it was started
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BusStatistics {
+    pub frames: u32,
+    /// Frames lost because the RX FIFO was full
+    pub overflows: u32,
+    pub bus_errors: u32,
+    pub invalid_messages: u32,
+    /// Error free frames counted by the controller in `CiBDIAG1`, wrapping at 65535
+    pub error_free_frames: u16,
+    /// Receive error counter of the arbitration phase
+    pub receive_errors: u8,
+    /// Receive error counter of the data phase
+    pub data_receive_errors: u8,
+}
+
+/// Passive view of the bus in Listen Only mode
+///
+/// The controller neither sends frames nor acknowledges or flags errors, and the monitor borrows
+/// the driver so that nothing else can queue a frame while it is alive. Call
+/// [`stop`](Self::stop) to go back to Configuration mode.
+pub struct Monitor<'a, SPI, INT> {
+    driver: &'a mut MCP25xxFD<SPI, INT>,
+    statistics: BusStatistics,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Start monitoring the bus
+    ///
+    /// RX FIFO 1 is set up to receive every frame through filter 0 and all other filters are
+    /// disabled, so the FIFO and filter configuration has to be applied again afterwards.
+    pub async fn monitor(&mut self, config: &MonitorConfig) -> Result<Monitor<'_, SPI, INT>, Error<SPI>> {
+        if !(1..=32).contains(&config.fifo_size) {
+            return Err(Error::ControllerError("Invalid FIFO size"));
+        }
+        self.enter_configuration_mode().await?;
+
+        // The TEF and TXQ come before FIFO 1 in RAM, the FIFOs after it are left unused
+        let mut ram = config.fifo_size as u16 * (8 + config.payload_size.bytes() as u16);
+        let can_control: CANControl = self.read_register().await?;
+        if can_control.stef() {
+            let tef: TransmitEventFIFOControl = self.read_register().await?;
+            ram += (tef.fsize() as u16 + 1) * if tef.teftsen() { 12 } else { 8 };
+        }
+        if can_control.txqen() {
+            let txq: TransmitQueueControl = self.read_register().await?;
+            ram += (txq.fsize() as u16 + 1) * (8 + txq.plsize().bytes() as u16);
+        }
+        if ram > RAM_SIZE {
+            return Err(Error::ControllerError("Monitor FIFO doesn't fit in RAM"));
+        }
+
+        for filter in 0..32 {
+            self.write_register_byte(FilterControl::<0>::ADDRESS + filter, 0).await?;
+        }
+        self.configure_fifo(FIFOConfig::<MONITOR_FIFO>::rx_with_size(config.fifo_size, config.payload_size)).await?;
+        self.configure_filter(
+            FilterConfig::<0, MONITOR_FIFO>::from_id(StandardId::ZERO),
+            MaskConfig::<0>::match_anything(),
+        ).await?;
+
+        // Start counting from a clean slate
+        self.clear_interrupt_flags(INTERRUPT_CERRIF | INTERRUPT_IVMIF).await?;
+        self.write_register(BusDiagnostic1::new()).await?;
+
+        self.set_mode(OperationMode::ListenOnly).await?;
+        Ok(Monitor { driver: self, statistics: BusStatistics::default() })
+    }
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> Monitor<'_, SPI, INT> {
+    /// The next frame or error seen on the bus, or `None` if there is nothing new
+    ///
+    /// Errors are reported before frames.
+    pub async fn receive(&mut self) -> Result<Option<MonitorEvent>, Error<SPI>> {
+        let interrupts: Interrupts = self.driver.read_register().await?;
+        if interrupts.cerrif() {
+            let diagnostic: BusDiagnostic1 = self.driver.read_register().await?;
+            self.driver.clear_bus_error_flags().await?;
+            self.driver.clear_interrupt_flags(INTERRUPT_CERRIF).await?;
+            self.statistics.bus_errors = self.statistics.bus_errors.saturating_add(1);
+            return Ok(Some(MonitorEvent::BusError(diagnostic.into())));
+        }
+        if interrupts.ivmif() {
+            self.driver.clear_interrupt_flags(INTERRUPT_IVMIF).await?;
+            self.statistics.invalid_messages = self.statistics.invalid_messages.saturating_add(1);
+            return Ok(Some(MonitorEvent::InvalidMessage));
+        }
+
+        let status: FIFOStatus<MONITOR_FIFO> = self.driver.read_register().await?;
+        if !status.contents.tfnrfnif() {
+            return Ok(None);
+        }
+        let overflows = self.driver.receive_overflows().count(MONITOR_FIFO);
+        let frame = self.driver.receive_from_fifo(MONITOR_FIFO).await?;
+        let new_overflows = self.driver.receive_overflows().count(MONITOR_FIFO) - overflows;
+        self.statistics.overflows = self.statistics.overflows.saturating_add(new_overflows);
+        Ok(frame.map(|(_, frame)| {
+            self.statistics.frames = self.statistics.frames.saturating_add(1);
+            MonitorEvent::Frame(frame)
+        }))
+    }
+
+    /// Counts kept by the monitor, along with the controller's own counters
+    pub async fn statistics(&mut self) -> Result<BusStatistics, Error<SPI>> {
+        let diagnostic0: BusDiagnostic0 = self.driver.read_register().await?;
+        let diagnostic1: BusDiagnostic1 = self.driver.read_register().await?;
+        Ok(BusStatistics {
+            error_free_frames: diagnostic1.efmsgcnt(),
+            receive_errors: diagnostic0.nrerrcnt(),
+            data_receive_errors: diagnostic0.drerrcnt(),
+            ..self.statistics
+        })
+    }
+
+    /// Stop monitoring and go back to Configuration mode
+    pub async fn stop(self) -> Result<(), Error<SPI>> {
+        self.driver.enter_configuration_mode().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use embedded_hal_async::spi::SpiDevice;
+use crate::interrupt::InterruptPins;
+use crate::registers::*;
+use crate::{fifo_status_address, Error, MCP25xxFD};
+
+// RXOVIF bit in the first byte of CiFIFOSTAm
+const FIFO_RXOVIF: u8 = 1 << 3;
+
+/// RX FIFO overflows seen by the driver
+///
+/// The controller only flags that a FIFO overflowed, not how many frames were dropped, so each
+/// count is the number of times at least one frame was lost.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReceiveOverflows {
+    counts: [u32; 31],
+    /// Bitmask of FIFOs that overflowed since the application last took them
+    pending: u32,
+}
+impl ReceiveOverflows {
+    /// Overflows of RX FIFO `fifo` (1 to 31)
+    pub fn count(&self, fifo: u8) -> u32 {
+        match fifo {
+            1..=31 => self.counts[fifo as usize - 1],
+            _ => 0,
+        }
+    }
+    /// Overflows of all RX FIFOs
+    pub fn total(&self) -> u32 {
+        self.counts.iter().fold(0, |total, count| total.saturating_add(*count))
+    }
+    fn record(&mut self, fifo: u8) {
+        let count = &mut self.counts[fifo as usize - 1];
+        *count = count.saturating_add(1);
+        self.pending |= 1 << fifo;
+    }
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Overflow counters, updated whenever a frame is received and by
+    /// [`check_receive_overflows`](Self::check_receive_overflows)
+    pub fn receive_overflows(&self) -> &ReceiveOverflows {
+        &self.rx_overflows
+    }
+
+    /// Take the lowest numbered RX FIFO that overflowed since it was last taken
+    pub fn take_receive_overflow(&mut self) -> Option<u8> {
+        let pending = self.rx_overflows.pending;
+        if pending == 0 {
+            return None;
+        }
+        let fifo = pending.trailing_zeros() as u8;
+        self.rx_overflows.pending &= !(1 << fifo);
+        Some(fifo)
+    }
+
+    pub fn clear_receive_overflows(&mut self) {
+        self.rx_overflows = ReceiveOverflows::default();
+    }
+
+    /// Check and clear the overflow flags of all RX FIFOs, e.g. after
+    /// [`InterruptEvent::ReceiveFIFOOverflow`](crate::interrupt::InterruptEvent::ReceiveFIFOOverflow)
+    ///
+    /// `CiRXOVIF` only shows FIFOs with the overflow interrupt enabled in
+    /// [`FIFOInterrupts::overflow`](crate::config::FIFOInterrupts::overflow). Other FIFOs are
+    /// checked as frames are received from them.
+    pub async fn check_receive_overflows(&mut self) -> Result<(), Error<SPI>> {
+        let overflows: ReceiveOverflowInterruptStatus = self.read_register().await?;
+        let pending = u32::from_le_bytes(overflows.serialize());
+        for fifo in 1..32 {
+            if pending & (1 << fifo) != 0 {
+                self.clear_receive_overflow(fifo).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record an overflow of RX FIFO `M` if it is flagged
+    pub(crate) async fn check_fifo_overflow<const M: u8>(&mut self) -> Result<(), Error<SPI>> {
+        let status: FIFOStatus<M> = self.read_register().await?;
+        if status.contents.rxovif() {
+            self.clear_receive_overflow(M).await?;
+        }
+        Ok(())
+    }
+
+    async fn clear_receive_overflow(&mut self, fifo: u8) -> Result<(), Error<SPI>> {
+        // Writing 1 to a flag leaves it unchanged
+        self.write_register_byte(fifo_status_address(fifo), !FIFO_RXOVIF).await?;
+        self.rx_overflows.record(fifo);
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use embedded_hal_async::spi::SpiDevice;
+use crate::interrupt::{InterruptPins, INTERRUPT_WAKIF};
+use crate::registers::*;
+use crate::{Error, MCP25xxFD};
+
+/// What to do with transmissions still pending when entering Sleep mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PendingTransmissions {
+    /// Wait for every pending message to be sent
+    Flush,
+    /// Abort every pending message, see [`MCP25xxFD::abort_all`]
+    Abort,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SleepConfig {
+    pub pending_transmissions: PendingTransmissions,
+    /// Enter Low Power Mode instead of Sleep mode (MCP2518FD and MCP251863 only)
+    ///
+    /// The controller goes through a reset when waking from Low Power Mode, so it must be set up
+    /// again with [`MCP25xxFD::reset_and_apply_config`] afterwards.
+    pub low_power: bool,
+    /// Wake up on bus activity and raise the wake-up interrupt
+    pub wake_on_bus: bool,
+    /// Filter out short glitches on RXCAN while asleep
+    pub wake_up_filter: Option<WakeUpFilterTime>,
+}
+impl Default for SleepConfig {
+    fn default() -> Self {
+        Self {
+            pending_transmissions: PendingTransmissions::Flush,
+            low_power: false,
+            wake_on_bus: true,
+            wake_up_filter: None,
+        }
+    }
+}
+
+/// What woke the controller up
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeUpSource {
+    /// Activity on the CAN bus
+    Bus,
+    /// A call to [`MCP25xxFD::wake_up`]
+    Request,
+}
+
+/// State saved while the controller is asleep
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct SleepState {
+    mode: OperationMode,
+    low_power: bool,
+    /// `WAKFIL` and `WFT` before sleeping
+    wake_up_filter: (bool, WakeUpFilterTime),
+    /// `WAKIE` before sleeping
+    wake_up_interrupt: bool,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Put the controller to sleep, remembering the current operation mode so that
+    /// [`wake_up`](Self::wake_up) can restore it
+    pub async fn sleep(&mut self, config: &SleepConfig) -> Result<(), Error<SPI>> {
+        let can_control: CANControl = self.read_register().await?;
+        let mode = can_control.opmode();
+        if mode == OperationMode::Sleep {
+            return Err(Error::ControllerError("Controller is already asleep"));
+        }
+
+        match config.pending_transmissions {
+            PendingTransmissions::Flush => self.wait_for_transmissions().await?,
+            PendingTransmissions::Abort => {
+                self.abort_all().await?;
+            },
+        }
+
+        let mut can_control: CANControl = self.read_register().await?;
+        let wake_up_filter = (can_control.wakfil(), can_control.wft());
+        can_control.set_wakfil(config.wake_up_filter.is_some());
+        if let Some(filter_time) = config.wake_up_filter {
+            can_control.set_wft(filter_time);
+        }
+        self.write_register(can_control).await?;
+
+        self.clear_interrupt_flags(INTERRUPT_WAKIF).await?;
+        let mut interrupts: Interrupts = self.read_register().await?;
+        let wake_up_interrupt = interrupts.wakeie();
+        interrupts.set_wakeie(config.wake_on_bus);
+        self.write_interrupt_enables(interrupts).await?;
+
+        let mut oscillator: OscillatorControl = self.read_register().await?;
+        oscillator.set_lpmen(config.low_power);
+        self.write_register(oscillator).await?;
+
+        self.set_mode(OperationMode::Sleep).await?;
+        self.sleep_state = Some(SleepState { mode, low_power: config.low_power, wake_up_filter, wake_up_interrupt });
+        Ok(())
+    }
+
+    /// Wake the controller up, either after it raised the wake-up interrupt or on request
+    ///
+    /// Once the oscillator is running again, the operation mode, wake-up filter and wake-up
+    /// interrupt enable from before [`sleep`](Self::sleep) are restored.
+    ///
+    /// Leaving Low Power Mode resets the controller, so its whole configuration is lost and nothing
+    /// is restored. Call [`reset_and_apply_config`](Self::reset_and_apply_config) again afterwards.
+    pub async fn wake_up(&mut self) -> Result<WakeUpSource, Error<SPI>> {
+        let Some(state) = self.sleep_state else {
+            return Err(Error::ControllerError("Controller is not asleep"));
+        };
+
+        let interrupts: Interrupts = self.read_register().await?;
+        let source = if interrupts.wakif() { WakeUpSource::Bus } else { WakeUpSource::Request };
+
+        // Writing to OSC wakes the controller up if it was still asleep
+        let mut oscillator: OscillatorControl = self.read_register().await?;
+        oscillator.set_oscdis(false);
+        oscillator.set_lpmen(false);
+        self.write_register(oscillator).await?;
+        self.wait_for_clock(oscillator.pllen()).await?;
+        self.sleep_state = None;
+
+        if state.low_power {
+            return Ok(source);
+        }
+
+        self.clear_interrupt_flags(INTERRUPT_WAKIF).await?;
+        let mut interrupts: Interrupts = self.read_register().await?;
+        interrupts.set_wakeie(state.wake_up_interrupt);
+        self.write_interrupt_enables(interrupts).await?;
+
+        let mut can_control: CANControl = self.read_register().await?;
+        let (wake_up_filter, filter_time) = state.wake_up_filter;
+        can_control.set_wakfil(wake_up_filter);
+        can_control.set_wft(filter_time);
+        // REQOP still asks for Sleep mode, which would send the controller straight back to sleep
+        can_control.set_reqop(OperationMode::Configuration);
+        self.write_register(can_control).await?;
+
+        self.set_mode(state.mode).await?;
+        Ok(source)
+    }
+
+    /// Whether [`sleep`](Self::sleep) was called without a matching [`wake_up`](Self::wake_up)
+    pub fn is_asleep(&self) -> bool {
+        self.sleep_state.is_some()
+    }
+
+    /// Wait until the controller has no transmit requests left pending
+    pub(crate) async fn wait_for_transmissions(&mut self) -> Result<(), Error<SPI>> {
+        const TRANSMIT_ATTEMPTS: usize = 10_000;
+
+        for _ in 0..TRANSMIT_ATTEMPTS {
+            let pending: TransmitRequest = self.read_register().await?;
+            if u32::from_le_bytes(pending.serialize()) == 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::ControllerError("Pending transmissions did not complete"))
+    }
+}
@@ -154,6 +154,7 @@ pub struct ReceiveMessageObjectHeader {
 
 /// Clock Output Divisor
 #[derive(BitfieldSpecifier, PartialEq, Eq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[bits = 2]
 pub enum ClockOutputDivisor {
     DivideBy1 = 0b00,
@@ -312,8 +313,21 @@ impl RegisterAddress for DeviceID {
     const ADDRESS: u16 = 0xE14;
 }
 
+/// Wake-up Filter Time
+/// See the device's electrical characteristics for the filter time of each setting
+#[derive(BitfieldSpecifier, PartialEq, Eq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[bits = 2]
+pub enum WakeUpFilterTime {
+    T00Filter = 0b00,
+    T01Filter = 0b01,
+    T10Filter = 0b10,
+    T11Filter = 0b11,
+}
+
 /// Request Operation mode
 #[derive(BitfieldSpecifier, PartialEq, Eq, Copy, Clone, Debug,)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[bits = 3]
 pub enum OperationMode {
     Normal = 0b000,
@@ -339,7 +353,7 @@ pub struct CANControl {
     /// Enable CAN Bus Line Wake-up Filter
     pub wakfil: bool,
     /// Selectable Wake-up Filter Time
-    pub wft: B2,
+    pub wft: WakeUpFilterTime,
     /// CAN Module is Busy
     #[skip(setters)]
     pub busy: bool,
@@ -407,6 +421,18 @@ impl RegisterAddress for DataBitTimeConfig {
     const ADDRESS: u16 = 0x008;
 }
 
+/// Transmitter Delay Compensation Mode
+#[derive(BitfieldSpecifier, PartialEq, Eq, Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[bits = 2]
+pub enum DelayCompensationMode {
+    #[default]
+    Disabled = 0b00,
+    Manual = 0b01,
+    Auto1 = 0b10,
+    Auto2 = 0b11,
+}
+
 #[bitfield(bits = 32)]
 #[derive(BitfieldSpecifier, Copy, Clone, Debug, Default)]
 pub struct TransmitterDelayCompensation {
@@ -417,7 +443,7 @@ pub struct TransmitterDelayCompensation {
     pub tdco: B7,
     #[skip] __: B1,
     /// Transmitter Delay Compensation Mode bits; Secondary Sample Point (SSP)
-    pub tdcmod: B2,
+    pub tdcmod: DelayCompensationMode,
     #[skip] __: B6,
     /// Enable 12-Bit SID in CAN FD Base Format Messages
     pub sid11en: bool,
@@ -967,6 +993,20 @@ pub enum PayloadSize {
     Bytes48 = 0b110,
     Bytes64 = 0b111,
 }
+impl PayloadSize {
+    pub const fn bytes(&self) -> usize {
+        match self {
+            PayloadSize::Bytes8 => 8,
+            PayloadSize::Bytes12 => 12,
+            PayloadSize::Bytes16 => 16,
+            PayloadSize::Bytes20 => 20,
+            PayloadSize::Bytes24 => 24,
+            PayloadSize::Bytes32 => 32,
+            PayloadSize::Bytes48 => 48,
+            PayloadSize::Bytes64 => 64,
+        }
+    }
+}
 
 
 #[bitfield(bits = 32)]
@@ -1089,7 +1129,7 @@ pub struct FIFOControl<const M: u8> {
 }
 
 impl<const M: u8> RegisterAddress for FIFOControl<M> {
-    const ADDRESS: u16 = 0x05C + 12 * (M as u16 - 1); // M is 1-indexed
+    const ADDRESS: u16 = 0x050 + 12 * M as u16; // M = 0 is the TXQ, which shares the layout
 }
 impl<const M: u8> Register for FIFOControl<M> {
     type Bitfield = FIFOControlM;
@@ -1132,7 +1172,7 @@ pub struct FIFOStatus<const M: u8> {
     pub contents: FIFOStatusM,
 }
 impl<const M: u8> RegisterAddress for FIFOStatus<M> {
-    const ADDRESS: u16 = 0x060 + 12 * (M as u16 - 1); // M is 1-indexed
+    const ADDRESS: u16 = 0x054 + 12 * M as u16; // M = 0 is the TXQ, which shares the layout
 }
 impl<const M: u8> Register for FIFOStatus<M> {
     type Bitfield = FIFOStatusM;
@@ -1157,7 +1197,7 @@ pub struct FIFOUserAddress<const M: u8> {
     pub contents: FIFOUserAddressM,
 }
 impl<const M: u8> RegisterAddress for FIFOUserAddress<M> {
-    const ADDRESS: u16 = 0x064 + 12 * (M as u16 - 1); // M is 1-indexed
+    const ADDRESS: u16 = 0x058 + 12 * M as u16; // M = 0 is the TXQ, which shares the layout
 }
 impl<const M: u8> Register for FIFOUserAddress<M> {
     type Bitfield = FIFOUserAddressM;
@@ -0,0 +1,223 @@
+use embedded_can::{ExtendedId, Id, StandardId};
+use embedded_hal_async::spi::SpiDevice;
+use crate::config::{FIFOConfig, FilterConfig, MaskConfig};
+use crate::frame::Frame;
+use crate::interrupt::InterruptPins;
+use crate::registers::*;
+use crate::{fifo_control_address, Error, MCP25xxFD};
+
+const TX_FIFO: u8 = 1;
+const RX_FIFO: u8 = 2;
+/// Number of `CiFLTCONm` registers, each holding four filters
+const FILTER_CONTROL_REGISTERS: u16 = 8;
+// FRESET bit of CiFIFOCONm
+const FIFO_FRESET: u32 = 1 << 10;
+
+/// Loopback mode to run [`MCP25xxFD::self_test`] in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoopbackMode {
+    /// Frames are looped back inside the controller, the bus is left alone
+    Internal,
+    /// Frames go out through TXCAN and come back through the transceiver on RXCAN, so the bus
+    /// should be quiet and terminated
+    External,
+}
+
+/// Test frames sent by [`MCP25xxFD::self_test`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestFrame {
+    /// Standard identifier, 8 data bytes
+    ClassicStandard,
+    /// Extended identifier, 8 data bytes
+    ClassicExtended,
+    /// Standard identifier, 64 data bytes at the data bit rate
+    FDStandard,
+    /// Extended identifier, 64 data bytes at the data bit rate
+    FDExtended,
+}
+impl TestFrame {
+    const ALL: [TestFrame; 4] = [TestFrame::ClassicStandard, TestFrame::ClassicExtended, TestFrame::FDStandard, TestFrame::FDExtended];
+
+    fn frame(&self) -> Frame {
+        let standard: Id = StandardId::new(0x5A5).unwrap().into();
+        let extended: Id = ExtendedId::new(0x0AA5_55AA).unwrap().into();
+        let mut data = [0u8; 64];
+        // Alternating bits and a running counter, so stuck or swapped bits show up
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = if i % 2 == 0 { 0xA5 ^ i as u8 } else { 0x5A ^ i as u8 };
+        }
+        let (id, length) = match self {
+            TestFrame::ClassicStandard => (standard, 8),
+            TestFrame::ClassicExtended => (extended, 8),
+            TestFrame::FDStandard => (standard, 64),
+            TestFrame::FDExtended => (extended, 64),
+        };
+        Frame::new(id, &data[..length]).unwrap()
+    }
+}
+
+/// How a test frame came back
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestOutcome {
+    /// Identical to the frame sent
+    Passed,
+    /// Nothing arrived in the RX FIFO
+    NotReceived,
+    /// The identifier or its type differs
+    IdMismatch { received: u32 },
+    /// The frame came back as a different format, with the `FDF` and `BRS` bits as received
+    FormatMismatch { fd: bool, bit_rate_switch: bool },
+    /// The data length code differs
+    LengthMismatch { received: DataLengthCode },
+    /// The data differs, starting at byte `index`
+    DataMismatch { index: u8, expected: u8, received: u8 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SelfTestReport {
+    pub mode: LoopbackMode,
+    pub results: [(TestFrame, TestOutcome); 4],
+}
+impl SelfTestReport {
+    /// Whether every test frame came back unchanged
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, outcome)| *outcome == TestOutcome::Passed)
+    }
+}
+
+/// Registers changed by the self-test, written back afterwards
+struct SavedConfig {
+    mode: OperationMode,
+    filter_control: [[u8; 4]; FILTER_CONTROL_REGISTERS as usize],
+    filter_object: FilterObject<0>,
+    mask: Mask<0>,
+    tx_fifo: FIFOControl<TX_FIFO>,
+    rx_fifo: FIFOControl<RX_FIFO>,
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Send classic and FD frames with standard and extended identifiers in a loopback mode and
+    /// check that they come back bit for bit
+    ///
+    /// FIFO 1 and 2 and filter 0 are borrowed for the test, and all other filters are disabled
+    /// while it runs. Their configuration and the operation mode are restored afterwards, but
+    /// messages waiting in FIFOs are lost since the FIFOs are reset.
+    pub async fn self_test(&mut self, mode: LoopbackMode) -> Result<SelfTestReport, Error<SPI>> {
+        let saved = self.save_test_config().await?;
+        let results = self.run_self_test(mode).await;
+        // Restored on errors too, the first error is the one reported
+        let restored = self.restore_test_config(saved).await;
+        let results = results?;
+        restored?;
+        Ok(SelfTestReport { mode, results })
+    }
+
+    async fn run_self_test(&mut self, mode: LoopbackMode) -> Result<[(TestFrame, TestOutcome); 4], Error<SPI>> {
+        for register in 0..FILTER_CONTROL_REGISTERS {
+            self.write_register_at(FilterControl::<0>::ADDRESS + 4 * register, [0; 4]).await?;
+        }
+        self.configure_fifo(FIFOConfig::<TX_FIFO>::tx_with_size(1, PayloadSize::Bytes64)).await?;
+        self.configure_fifo(FIFOConfig::<RX_FIFO>::rx_with_size(1, PayloadSize::Bytes64)).await?;
+        self.configure_filter(
+            FilterConfig::<0, RX_FIFO>::from_id(StandardId::ZERO),
+            MaskConfig::<0>::match_anything(),
+        ).await?;
+
+        let operation_mode = match mode {
+            LoopbackMode::Internal => OperationMode::InternalLoopback,
+            LoopbackMode::External => OperationMode::ExternalLoopback,
+        };
+        self.set_mode(operation_mode).await?;
+        self.wait_for_mode(operation_mode).await?;
+
+        let mut results = TestFrame::ALL.map(|test_frame| (test_frame, TestOutcome::NotReceived));
+        for (test_frame, outcome) in results.iter_mut() {
+            *outcome = self.loop_back(&test_frame.frame()).await?;
+        }
+        Ok(results)
+    }
+
+    async fn loop_back(&mut self, sent: &Frame) -> Result<TestOutcome, Error<SPI>> {
+        const RECEIVE_ATTEMPTS: usize = 1000;
+
+        self.transmit::<TX_FIFO>(sent).await?;
+        for _ in 0..RECEIVE_ATTEMPTS {
+            let status: FIFOStatus<RX_FIFO> = self.read_register().await?;
+            if status.contents.tfnrfnif() {
+                // A Frame doesn't keep FDF and BRS, so they are read from the message object
+                let address = self.read_register::<FIFOUserAddress<RX_FIFO>>().await?.contents.fifoua() as u16;
+                let header = ReceiveMessageObjectHeader::from_bytes(self.read_bytes(address).await?);
+                return Ok(match self.receive_from_fifo(RX_FIFO).await? {
+                    Some((_, received)) => compare_frames(sent, &received, &header),
+                    None => TestOutcome::NotReceived,
+                });
+            }
+        }
+        // Don't leave the frame behind for the restored configuration
+        self.abort_fifo(TX_FIFO).await?;
+        Ok(TestOutcome::NotReceived)
+    }
+
+    async fn save_test_config(&mut self) -> Result<SavedConfig, Error<SPI>> {
+        let mode = self.enter_configuration_mode().await?;
+        match self.read_test_config(mode).await {
+            Ok(saved) => Ok(saved),
+            Err(error) => {
+                // Nothing but the mode was changed yet
+                self.set_mode(mode).await.ok();
+                Err(error)
+            },
+        }
+    }
+
+    async fn read_test_config(&mut self, mode: OperationMode) -> Result<SavedConfig, Error<SPI>> {
+        let mut filter_control = [[0; 4]; FILTER_CONTROL_REGISTERS as usize];
+        for (register, contents) in filter_control.iter_mut().enumerate() {
+            *contents = self.read_register_at(FilterControl::<0>::ADDRESS + 4 * register as u16).await?;
+        }
+        Ok(SavedConfig {
+            mode,
+            filter_control,
+            filter_object: self.read_register().await?,
+            mask: self.read_register().await?,
+            tx_fifo: self.read_register().await?,
+            rx_fifo: self.read_register().await?,
+        })
+    }
+
+    async fn restore_test_config(&mut self, saved: SavedConfig) -> Result<(), Error<SPI>> {
+        self.enter_configuration_mode().await?;
+        self.write_register(saved.filter_object).await?;
+        self.write_register(saved.mask).await?;
+        for (fifo, control) in [(TX_FIFO, saved.tx_fifo.serialize()), (RX_FIFO, saved.rx_fifo.serialize())] {
+            let control = u32::from_le_bytes(control) | FIFO_FRESET;
+            self.write_register_at(fifo_control_address(fifo), control.to_le_bytes()).await?;
+        }
+        for (register, contents) in saved.filter_control.into_iter().enumerate() {
+            self.write_register_at(FilterControl::<0>::ADDRESS + 4 * register as u16, contents).await?;
+        }
+        self.set_mode(saved.mode).await
+    }
+}
+
+fn compare_frames(sent: &Frame, received: &Frame, received_header: &ReceiveMessageObjectHeader) -> TestOutcome {
+    if sent.id() != received.id() {
+        return TestOutcome::IdMismatch { received: received.raw_id() };
+    }
+    let (sent_header, _) = sent.as_components();
+    if (sent_header.fdf(), sent_header.brs()) != (received_header.fdf(), received_header.brs()) {
+        return TestOutcome::FormatMismatch { fd: received_header.fdf(), bit_rate_switch: received_header.brs() };
+    }
+    if sent.dlc() != received.dlc() {
+        return TestOutcome::LengthMismatch { received: received.dlc() };
+    }
+    let mismatch = sent.data().iter().zip(received.data()).enumerate().find(|(_, (sent, received))| sent != received);
+    match mismatch {
+        Some((index, (expected, received))) => TestOutcome::DataMismatch { index: index as u8, expected: *expected, received: *received },
+        None => TestOutcome::Passed,
+    }
+}
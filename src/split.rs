@@ -0,0 +1,215 @@
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::{ErrorType, Operation, SpiDevice};
+use crate::crc::SPICRCCounters;
+use crate::ecc::ECCCounters;
+use crate::frame::Frame;
+use crate::interrupt::{InterruptPin, InterruptPins, NoInterruptPin, SeparateInterruptPins};
+use crate::overflow::ReceiveOverflows;
+use crate::transmit::{AbortRecord, AbortedMessages, TransmitOutcome, TransmitTicket};
+use crate::{Error, MCP25xxFD};
+
+/// Interrupt pins that can be divided between a [`TransmitHalf`] and a [`ReceiveHalf`]
+///
+/// A single shared INT pin can't be divided, as either half would wait on the other's interrupts.
+pub trait SplitInterruptPins: InterruptPins {
+    type RX: InterruptPins;
+    type TX: InterruptPins;
+
+    fn split(self) -> (Self::RX, Self::TX);
+}
+
+impl SplitInterruptPins for NoInterruptPin {
+    type RX = NoInterruptPin;
+    type TX = NoInterruptPin;
+
+    fn split(self) -> (NoInterruptPin, NoInterruptPin) {
+        (NoInterruptPin, NoInterruptPin)
+    }
+}
+
+impl<RX: Wait, TX: Wait> SplitInterruptPins for SeparateInterruptPins<RX, TX> {
+    type RX = InterruptPin<RX>;
+    type TX = InterruptPin<TX>;
+
+    fn split(self) -> (InterruptPin<RX>, InterruptPin<TX>) {
+        (InterruptPin(self.rx), InterruptPin(self.tx))
+    }
+}
+
+/// Storage for the SPI device of a split driver, which has to outlive both halves
+///
+/// `M` decides how the halves exclude each other, e.g. `CriticalSectionRawMutex` for halves
+/// running on different executors or `NoopRawMutex` for tasks on the same one. It can be kept in
+/// a `static`.
+pub struct SharedSPI<M: RawMutex, SPI> {
+    spi: Option<Mutex<M, SPI>>,
+}
+impl<M: RawMutex, SPI> SharedSPI<M, SPI> {
+    pub const fn new() -> Self {
+        Self { spi: None }
+    }
+}
+impl<M: RawMutex, SPI> Default for SharedSPI<M, SPI> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SPI device of one half, locking the shared device for each transaction
+pub struct SPIHandle<'a, M: RawMutex, SPI> {
+    spi: &'a Mutex<M, SPI>,
+}
+
+impl<M: RawMutex, SPI: SpiDevice> ErrorType for SPIHandle<'_, M, SPI> {
+    type Error = SPI::Error;
+}
+
+impl<M: RawMutex, SPI: SpiDevice> SpiDevice for SPIHandle<'_, M, SPI> {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SPI::Error> {
+        self.spi.lock().await.transaction(operations).await
+    }
+}
+
+/// Transmit side of a split driver, touching only TX FIFOs, the TXQ and the Transmit Event FIFO
+pub struct TransmitHalf<'a, M: RawMutex, SPI, INT> {
+    driver: MCP25xxFD<SPIHandle<'a, M, SPI>, INT>,
+}
+
+/// Receive side of a split driver, touching only RX FIFOs and the bus error flag
+pub struct ReceiveHalf<'a, M: RawMutex, SPI, INT> {
+    driver: MCP25xxFD<SPIHandle<'a, M, SPI>, INT>,
+}
+
+impl<SPI: SpiDevice, INT: SplitInterruptPins> MCP25xxFD<SPI, INT> {
+    /// Split a configured driver into halves that can transmit and receive at the same time,
+    /// e.g. from separate tasks
+    ///
+    /// The SPI device moves into `shared`, which both halves lock for every SPI transaction, and
+    /// the interrupt pins are divided between them. Configuration can't be changed while split,
+    /// and the halves can't be joined back into a driver, so configure everything first.
+    ///
+    /// Neither half can wake the controller up, so a driver that put it to sleep can't be split.
+    /// The ECC and SPI CRC counters are dropped, as neither half checks for those errors.
+    #[allow(clippy::type_complexity)]
+    pub fn split<M: RawMutex>(self, shared: &mut SharedSPI<M, SPI>) -> Result<(TransmitHalf<'_, M, SPI, INT::TX>, ReceiveHalf<'_, M, SPI, INT::RX>), Error<SPI>> {
+        let MCP25xxFD {
+            spi,
+            int,
+            gpio_pins,
+            interrupt_pins,
+            transceiver_standby,
+            sleep_state,
+            ecc_counters: _,
+            crc_counters: _,
+            crc_write_retries,
+            rx_overflows,
+            tx_sequence,
+            tx_aborts,
+            rx_draining,
+            ram_layout,
+        } = self;
+        if sleep_state.is_some() {
+            return Err(Error::ControllerError("Can't split while asleep"));
+        }
+        let (rx_int, tx_int) = int.split();
+        let spi: &Mutex<M, SPI> = shared.spi.insert(Mutex::new(spi));
+
+        let tx = MCP25xxFD {
+            spi: SPIHandle { spi },
+            int: tx_int,
+            gpio_pins,
+            interrupt_pins,
+            transceiver_standby,
+            sleep_state: None,
+            ecc_counters: ECCCounters::default(),
+            crc_counters: SPICRCCounters::default(),
+            crc_write_retries,
+            rx_overflows: ReceiveOverflows::default(),
+            tx_sequence,
+            tx_aborts,
+            rx_draining: 0,
+            ram_layout,
+        };
+        let rx = MCP25xxFD {
+            spi: SPIHandle { spi },
+            int: rx_int,
+            gpio_pins,
+            interrupt_pins,
+            transceiver_standby,
+            sleep_state: None,
+            ecc_counters: ECCCounters::default(),
+            crc_counters: SPICRCCounters::default(),
+            crc_write_retries,
+            rx_overflows,
+            tx_sequence: 0,
+            tx_aborts: [AbortRecord::default(); 32],
+            rx_draining,
+            ram_layout,
+        };
+        Ok((TransmitHalf { driver: tx }, ReceiveHalf { driver: rx }))
+    }
+}
+
+impl<'a, M: RawMutex, SPI: SpiDevice, INT: InterruptPins> TransmitHalf<'a, M, SPI, INT> {
+    /// See [`MCP25xxFD::transmit`]
+    pub async fn transmit<const F: u8>(&mut self, frame: &Frame) -> Result<(), Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.transmit::<F>(frame).await
+    }
+
+    /// See [`MCP25xxFD::transmit_tracked`]
+    pub async fn transmit_tracked<const F: u8>(&mut self, frame: &Frame) -> Result<TransmitTicket, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.transmit_tracked::<F>(frame).await
+    }
+
+    /// See [`MCP25xxFD::transmit_outcome`]
+    pub async fn transmit_outcome(&mut self, ticket: &TransmitTicket) -> Result<Option<TransmitOutcome>, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.transmit_outcome(ticket).await
+    }
+
+    /// See [`MCP25xxFD::wait_for_outcome`]
+    pub async fn wait_for_outcome(&mut self, ticket: &TransmitTicket, polls: usize) -> Result<TransmitOutcome, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.wait_for_outcome(ticket, polls).await
+    }
+
+    /// See [`MCP25xxFD::take_attempts_exhausted`]
+    pub async fn take_attempts_exhausted(&mut self) -> Result<u32, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.take_attempts_exhausted().await
+    }
+
+    /// See [`MCP25xxFD::abort_fifo`]
+    pub async fn abort_fifo(&mut self, fifo: u8) -> Result<AbortedMessages, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.abort_fifo(fifo).await
+    }
+
+    /// See [`MCP25xxFD::abort_transmit_queue`]
+    pub async fn abort_transmit_queue(&mut self) -> Result<AbortedMessages, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.abort_transmit_queue().await
+    }
+}
+
+impl<'a, M: RawMutex, SPI: SpiDevice, INT: InterruptPins> ReceiveHalf<'a, M, SPI, INT> {
+    /// See [`MCP25xxFD::receive`]
+    pub async fn receive(&mut self, fifo_restriction: Option<u8>) -> Result<Option<(u8, Frame)>, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.receive(fifo_restriction).await
+    }
+
+    /// See [`MCP25xxFD::receive_from_fifo`]
+    pub async fn receive_from_fifo(&mut self, fifo: u8) -> Result<Option<(u8, Frame)>, Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.receive_from_fifo(fifo).await
+    }
+
+    /// See [`MCP25xxFD::check_receive_overflows`]
+    pub async fn check_receive_overflows(&mut self) -> Result<(), Error<SPIHandle<'a, M, SPI>>> {
+        self.driver.check_receive_overflows().await
+    }
+
+    pub fn receive_overflows(&self) -> &ReceiveOverflows {
+        self.driver.receive_overflows()
+    }
+
+    pub fn take_receive_overflow(&mut self) -> Option<u8> {
+        self.driver.take_receive_overflow()
+    }
+}
@@ -0,0 +1,415 @@
+use embedded_hal_async::spi::SpiDevice;
+use crate::config::BandwidthSharing;
+use crate::frame::Frame;
+use crate::interrupt::InterruptPins;
+use crate::registers::*;
+use crate::{fifo_control_address, fifo_status_address, fifo_user_address, Error, MCP25xxFD, FIFO_TXEN};
+
+// Bits of the second byte of CiFIFOCONm
+const FIFO_TXREQ: u8 = 1 << 1;
+
+// Bits of the first byte of CiFIFOSTAm, at the same position in CiTXQSTA
+const FIFO_TFERFFIF: u8 = 1 << 2;
+const FIFO_TXATIF: u8 = 1 << 4;
+const FIFO_TXERR: u8 = 1 << 5;
+const FIFO_TXABT: u8 = 1 << 7;
+
+// UINC bit in the second byte of CiTEFCON
+const TEF_UINC: u8 = 1 << 0;
+
+/// Sequence numbers in message objects are 23 bits wide
+const SEQUENCE_MASK: u32 = 0x7F_FFFF;
+
+/// Messages withdrawn from a TX FIFO by an abort
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AbortedMessages {
+    /// TX FIFO, 0 for the TXQ
+    pub fifo: u8,
+    /// Message object the first aborted message was queued in
+    pub first_object: u8,
+    /// Number of aborted messages, queued in the objects following `first_object` in FIFO order
+    pub count: u8,
+}
+
+/// Messages withdrawn from every TX FIFO by [`MCP25xxFD::abort_all`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AbortReport {
+    fifos: [AbortedMessages; 32],
+}
+impl AbortReport {
+    /// Messages aborted in TX FIFO `fifo`, 0 for the TXQ
+    pub fn fifo(&self, fifo: u8) -> AbortedMessages {
+        self.fifos.get(fifo as usize).copied().unwrap_or_default()
+    }
+    /// FIFOs that had messages aborted
+    pub fn iter(&self) -> impl Iterator<Item = &AbortedMessages> {
+        self.fifos.iter().filter(|aborted| aborted.count > 0)
+    }
+    /// Number of aborted messages across all FIFOs
+    pub fn total(&self) -> u32 {
+        self.fifos.iter().map(|aborted| aborted.count as u32).sum()
+    }
+}
+
+/// Messages discarded by the last abort of a TX FIFO, and the number of such aborts so far
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct AbortRecord {
+    resets: u8,
+    aborted: AbortedMessages,
+}
+
+/// A frame queued by [`MCP25xxFD::transmit_tracked`], used to look up its outcome
+///
+/// The ticket identifies the message object the frame occupies, so its outcome has to be looked
+/// up before the FIFO wraps around and reuses the object.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TransmitTicket {
+    pub fifo: u8,
+    /// Message object the frame was queued in
+    pub object: u8,
+    /// Sequence number of the frame, as reported in the Transmit Event FIFO
+    pub sequence_number: u32,
+    /// RAM address of the first message object of the FIFO
+    fifo_start: u16,
+    object_size: u16,
+    fifo_size: u8,
+    /// Aborts that had reset the FIFO when the frame was queued
+    resets: u8,
+}
+
+/// What happened to a tracked frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransmitOutcome {
+    /// The frame was sent. The timestamp is taken from the Transmit Event FIFO when it is enabled
+    /// with timestamps.
+    Sent { timestamp: Option<u32> },
+    /// The frame was aborted before it could be sent
+    Aborted,
+    /// The frame is still queued, but its transmission is no longer requested, e.g. after its
+    /// attempts ran out and `TXATIF` was taken with
+    /// [`take_attempts_exhausted`](MCP25xxFD::take_attempts_exhausted). It goes out again with the
+    /// next transmit request of the FIFO.
+    NotRequested,
+    /// The retransmission attempts ran out without an error detected, so every attempt lost
+    /// arbitration
+    AttemptsExhausted,
+    /// The retransmission attempts ran out after errors during transmission
+    Error(BusError),
+}
+
+/// Last bus error recorded in `CiBDIAG1`, in either the arbitration or data phase
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusError {
+    /// Sent a dominant bit but monitored a recessive one
+    Bit0,
+    /// Sent a recessive bit outside of arbitration but monitored a dominant one
+    Bit1,
+    /// The frame wasn't acknowledged
+    Acknowledge,
+    /// A fixed format part of a frame had the wrong format
+    Form,
+    /// More than 5 equal bits in a row
+    Stuff,
+    /// CRC mismatch
+    CRC,
+    /// The controller went bus-off
+    BusOff,
+    /// No error flag is set
+    Unknown,
+}
+impl From<BusDiagnostic1> for BusError {
+    fn from(diagnostic: BusDiagnostic1) -> Self {
+        if diagnostic.txboerr() {
+            BusError::BusOff
+        } else if diagnostic.nackerr() {
+            BusError::Acknowledge
+        } else if diagnostic.nbit0err() || diagnostic.dbit0err() {
+            BusError::Bit0
+        } else if diagnostic.nbit1err() || diagnostic.dbit1err() {
+            BusError::Bit1
+        } else if diagnostic.nformerr() || diagnostic.dformerr() {
+            BusError::Form
+        } else if diagnostic.nstuferr() || diagnostic.dstuferr() {
+            BusError::Stuff
+        } else if diagnostic.ncrcerr() || diagnostic.dcrcerr() {
+            BusError::CRC
+        } else {
+            BusError::Unknown
+        }
+    }
+}
+
+impl<SPI: SpiDevice, INT: InterruptPins> MCP25xxFD<SPI, INT> {
+    /// Queue a frame in TX FIFO `M` like [`transmit`](Self::transmit), returning a ticket to look
+    /// up its outcome with
+    ///
+    /// The frame's sequence number is used if it has one, otherwise the driver numbers frames
+    /// itself. Only one frame in a FIFO should be tracked at a time, as the FIFO's status flags
+    /// cover all of its frames.
+    pub async fn transmit_tracked<const M: u8>(&mut self, frame: &Frame) -> Result<TransmitTicket, Error<SPI>> {
+        let sequence_number = match frame.sequence_number() {
+            Some(sequence_number) => sequence_number & SEQUENCE_MASK,
+            None => {
+                self.tx_sequence = (self.tx_sequence + 1) & SEQUENCE_MASK;
+                self.tx_sequence
+            },
+        };
+        let (header, data) = frame.as_components();
+
+        let address = self.next_message_object::<M>().await?;
+        let (fifo_start, object_size) = self.ram_layout().await?.tx_fifo(M);
+        let control: FIFOControl<M> = self.read_register().await?;
+        let fifo_size = control.contents.fsize() + 1;
+        let object = address.wrapping_sub(fifo_start) / object_size;
+        if object >= fifo_size as u16 {
+            return Err(Error::ControllerError("FIFO address outside of its message RAM"));
+        }
+
+        // Stale from an earlier frame, the other status bits clear when TXREQ is set
+        self.write_register_byte(fifo_status_address(M), !FIFO_TXATIF).await?;
+        self.queue_message_at::<M>(address, header.with_seq(sequence_number), data).await?;
+        Ok(TransmitTicket {
+            fifo: M,
+            object: object as u8,
+            sequence_number,
+            fifo_start,
+            object_size,
+            fifo_size,
+            resets: self.tx_aborts[M as usize].resets,
+        })
+    }
+
+    /// Outcome of a tracked frame, or `None` while it is still pending
+    ///
+    /// Reading the timestamp consumes Transmit Event FIFO entries up to the frame's, so outcomes
+    /// should be looked up in the order the frames were queued. A frame queued before its FIFO
+    /// was aborted twice can't be told apart from the frames queued since, and is an error.
+    pub async fn transmit_outcome(&mut self, ticket: &TransmitTicket) -> Result<Option<TransmitOutcome>, Error<SPI>> {
+        let size = ticket.fifo_size;
+        let record = self.tx_aborts[ticket.fifo as usize];
+        if record.resets != ticket.resets {
+            if record.resets != ticket.resets.wrapping_add(1) {
+                return Err(Error::ControllerError("FIFO was aborted more than once since the frame was queued"));
+            }
+            // Frames before the aborted ones were sent
+            let aborted = record.aborted;
+            if (ticket.object + size - aborted.first_object) % size < aborted.count {
+                return Ok(Some(TransmitOutcome::Aborted));
+            }
+            let timestamp = self.transmit_timestamp(ticket.sequence_number).await?;
+            return Ok(Some(TransmitOutcome::Sent { timestamp }));
+        }
+
+        // TXREQ clears as the flags are set, so reading it first doesn't miss the flags
+        let control = self.read_register_at(fifo_control_address(ticket.fifo)).await?;
+        let status = self.read_register_at(fifo_status_address(ticket.fifo)).await?;
+        let user_address = u32::from_le_bytes(self.read_register_at(fifo_user_address(ticket.fifo)).await?) as u16;
+
+        let next = status[1] & 0x1F;
+        let head = ((user_address.wrapping_sub(ticket.fifo_start) / ticket.object_size) as u8) % size;
+        let pending = status[0] & FIFO_TFERFFIF == 0 && {
+            // A full FIFO has its head back at the next message to send
+            let queued = match (head + size - next) % size {
+                0 => size,
+                queued => queued,
+            };
+            (ticket.object + size - next) % size < queued
+        };
+
+        if !pending {
+            let timestamp = self.transmit_timestamp(ticket.sequence_number).await?;
+            return Ok(Some(TransmitOutcome::Sent { timestamp }));
+        }
+        if control[1] & FIFO_TXREQ != 0 {
+            return Ok(None);
+        }
+        if status[0] & FIFO_TXABT != 0 {
+            Ok(Some(TransmitOutcome::Aborted))
+        }
+        else if status[0] & FIFO_TXATIF != 0 {
+            if status[0] & FIFO_TXERR != 0 {
+                let diagnostic: BusDiagnostic1 = self.read_register().await?;
+                Ok(Some(TransmitOutcome::Error(diagnostic.into())))
+            }
+            else {
+                Ok(Some(TransmitOutcome::AttemptsExhausted))
+            }
+        }
+        else {
+            Ok(Some(TransmitOutcome::NotRequested))
+        }
+    }
+
+    /// Look up the outcome of a tracked frame until it is known, giving up with an error after
+    /// `polls` lookups
+    ///
+    /// With an interrupt pin connected, each further lookup waits for the transmit interrupt. The
+    /// pin only asserts for this if an interrupt of the FIFO that fires with the outcome is
+    /// enabled, e.g. [`FIFOInterrupts::empty_full`] with [`InterruptConfig::transmit`], or
+    /// [`FIFOInterrupts::attempts_exhausted`] with [`InterruptConfig::transmit_attempt`].
+    ///
+    /// [`FIFOInterrupts::empty_full`]: crate::config::FIFOInterrupts::empty_full
+    /// [`FIFOInterrupts::attempts_exhausted`]: crate::config::FIFOInterrupts::attempts_exhausted
+    /// [`InterruptConfig::transmit`]: crate::config::InterruptConfig::transmit
+    /// [`InterruptConfig::transmit_attempt`]: crate::config::InterruptConfig::transmit_attempt
+    pub async fn wait_for_outcome(&mut self, ticket: &TransmitTicket, polls: usize) -> Result<TransmitOutcome, Error<SPI>> {
+        for poll in 0..polls {
+            if poll > 0 && INT::CONNECTED {
+                self.int.wait_for_transmit().await.map_err(Error::InterruptPinError)?;
+            }
+            if let Some(outcome) = self.transmit_outcome(ticket).await? {
+                return Ok(outcome);
+            }
+        }
+        Err(Error::ControllerError("Outcome of the transmission not known in time"))
+    }
+
+    /// Look for the Transmit Event FIFO entry of `sequence_number`, discarding the entries before it
+    async fn transmit_timestamp(&mut self, sequence_number: u32) -> Result<Option<u32>, Error<SPI>> {
+        let can_control: CANControl = self.read_register().await?;
+        if !can_control.stef() {
+            return Ok(None);
+        }
+        let tef_control: TransmitEventFIFOControl = self.read_register().await?;
+
+        for _ in 0..=tef_control.fsize() {
+            let tef_status: TransmitEventFIFOStatus = self.read_register().await?;
+            if !tef_status.tefneif() {
+                break;
+            }
+            let address = self.read_register::<TransmitEventFIFOUserAddress>().await?.tefua() as u16;
+            let event: [u8; 12] = self.read_bytes(address).await?;
+            self.write_register_byte(TransmitEventFIFOControl::ADDRESS + 1, TEF_UINC).await?;
+
+            let header = TransmitMessageObjectHeader::from_bytes(event[..8].try_into().unwrap());
+            if header.seq() == sequence_number {
+                let timestamp = u32::from_le_bytes(event[8..].try_into().unwrap());
+                return Ok(tef_control.teftsen().then_some(timestamp));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Change the delay between transmissions, going through Configuration mode
+    ///
+    /// Pending transmissions are held back until the previous mode is restored.
+    pub async fn set_bandwidth_sharing(&mut self, delay: BandwidthSharing) -> Result<(), Error<SPI>> {
+        let mode = self.enter_configuration_mode().await?;
+        let mut can_control: CANControl = self.read_register().await?;
+        can_control.set_txbws(delay.bits());
+        self.write_register(can_control).await?;
+        self.set_mode(mode).await
+    }
+
+    /// The delay between transmissions currently set
+    pub async fn bandwidth_sharing(&mut self) -> Result<BandwidthSharing, Error<SPI>> {
+        let can_control: CANControl = self.read_register().await?;
+        Ok(BandwidthSharing::from_bits(can_control.txbws()))
+    }
+
+    /// Take the TX FIFOs that ran out of retransmission attempts, as a bitmask with bit 0 for the
+    /// TXQ and bit `m` for FIFO `m`
+    ///
+    /// The `TXATIF` flags of those FIFOs are cleared. Only FIFOs with
+    /// [`FIFOInterrupts::attempts_exhausted`](crate::config::FIFOInterrupts::attempts_exhausted)
+    /// enabled show up in `CiTXATIF`.
+    pub async fn take_attempts_exhausted(&mut self) -> Result<u32, Error<SPI>> {
+        let attempts: TransmitAttemptInterruptStatus = self.read_register().await?;
+        let exhausted = u32::from_le_bytes(attempts.serialize());
+        for fifo in 0..32 {
+            if exhausted & (1 << fifo) != 0 {
+                // Writing 1 to a flag leaves it unchanged
+                self.write_register_byte(fifo_status_address(fifo), !FIFO_TXATIF).await?;
+            }
+        }
+        Ok(exhausted)
+    }
+
+    /// Abort the messages pending in TX FIFO `fifo` (1 to 31) and discard them
+    ///
+    /// A message already being sent can't be aborted. This waits for it to complete, so it is
+    /// not reported as aborted if it was sent successfully.
+    pub async fn abort_fifo(&mut self, fifo: u8) -> Result<AbortedMessages, Error<SPI>> {
+        if !(1..32).contains(&fifo) {
+            return Err(Error::ControllerError("Invalid FIFO"));
+        }
+        self.abort_tx_fifo(fifo).await
+    }
+
+    /// Abort the messages pending in the TXQ and discard them, like [`abort_fifo`](Self::abort_fifo)
+    pub async fn abort_transmit_queue(&mut self) -> Result<AbortedMessages, Error<SPI>> {
+        self.abort_tx_fifo(0).await
+    }
+
+    /// Abort all pending transmissions in the TXQ and every TX FIFO and discard them
+    pub async fn abort_all(&mut self) -> Result<AbortReport, Error<SPI>> {
+        let mut can_control: CANControl = self.read_register().await?;
+        can_control.set_abat(true);
+        self.write_register(can_control).await?;
+        self.wait_for_transmissions().await?;
+        can_control.set_abat(false);
+        self.write_register(can_control).await?;
+
+        let mut report = AbortReport::default();
+        for fifo in 0..32 {
+            report.fifos[fifo as usize] = self.discard_aborted(fifo).await?;
+        }
+        Ok(report)
+    }
+
+    async fn abort_tx_fifo(&mut self, fifo: u8) -> Result<AbortedMessages, Error<SPI>> {
+        const ABORT_ATTEMPTS: usize = 10_000;
+
+        let control_address = fifo_control_address(fifo);
+        let control = self.read_register_at(control_address).await?;
+        if fifo != 0 && control[0] & FIFO_TXEN == 0 {
+            return Err(Error::ControllerError("FIFO is not a TX FIFO"));
+        }
+
+        // Clearing TXREQ requests the abort, the other bits in this byte have no effect when 0
+        self.write_register_byte(control_address + 1, 0).await?;
+        for _ in 0..ABORT_ATTEMPTS {
+            let control = self.read_register_at(control_address).await?;
+            if control[1] & FIFO_TXREQ == 0 {
+                return self.discard_aborted(fifo).await;
+            }
+        }
+        Err(Error::ControllerError("Abort did not complete"))
+    }
+
+    /// Report the messages left in a FIFO flagged as aborted, and reset it so that they aren't sent
+    /// with the next transmit request
+    async fn discard_aborted(&mut self, fifo: u8) -> Result<AbortedMessages, Error<SPI>> {
+        let status = self.read_register_at(fifo_status_address(fifo)).await?;
+        if status[0] & FIFO_TXABT == 0 || status[0] & FIFO_TFERFFIF != 0 {
+            return Ok(AbortedMessages { fifo, ..Default::default() });
+        }
+
+        let control = self.read_register_at(fifo_control_address(fifo)).await?;
+        let size = (control[3] & 0x1F) + 1;
+        let first_object = status[1] & 0x1F;
+        let user_address = u32::from_le_bytes(self.read_register_at(fifo_user_address(fifo)).await?) as u16;
+        let (fifo_start, object_size) = self.ram_layout().await?.tx_fifo(fifo);
+        let head = user_address.wrapping_sub(fifo_start) / object_size;
+        if head >= size as u16 {
+            return Err(Error::ControllerError("FIFO address outside of its message RAM"));
+        }
+        let head = head as u8;
+        // A full FIFO has its head back at the first pending message
+        let count = match (head + size - first_object) % size {
+            0 => size,
+            count => count,
+        };
+
+        self.reset_fifo_at(fifo_control_address(fifo)).await?;
+        let aborted = AbortedMessages { fifo, first_object, count };
+        let record = &mut self.tx_aborts[fifo as usize];
+        *record = AbortRecord { resets: record.resets.wrapping_add(1), aborted };
+        Ok(aborted)
+    }
+}
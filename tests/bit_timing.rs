@@ -0,0 +1,142 @@
+use mcp25xxfd::config::*;
+use mcp25xxfd::registers::*;
+
+#[test]
+fn test_decode_preset() {
+    let bit_rate = BitRate { arbitration: ArbitrationBitRate::Rate500K, data: DataBitRate::Rate2M };
+    let info = bit_rate.get_config(&Clock::Clock40MHz).unwrap().decode(&Clock::Clock40MHz);
+
+    assert_eq!(info.arbitration, PhaseTiming { bit_rate: 500_000, time_quanta: 80, sample_point: 800, sjw: 16 });
+    assert_eq!(info.data, PhaseTiming { bit_rate: 2_000_000, time_quanta: 20, sample_point: 800, sjw: 4 });
+    assert_eq!(info.tdc_mode, DelayCompensationMode::Auto1);
+    assert_eq!(info.tdc_offset, 15);
+}
+
+#[test]
+fn test_raw_config_round_trip() {
+    let config = BitRateConfig {
+        arbitration_brp: 1,
+        arbitration_tseg1: 30,
+        arbitration_tseg2: 7,
+        arbitration_sjw: 3,
+        data_brp: 0,
+        data_tseg1: 12,
+        data_tseg2: 5,
+        data_sjw: 2,
+        tdc_offset: -3,
+        tdc_value: 4,
+        tdc_mode: DelayCompensationMode::Manual,
+    };
+    assert!(config.is_valid());
+
+    let tdc = TransmitterDelayCompensation::new()
+        .with_tdcmod(config.tdc_mode)
+        .with_tdco(config.tdc_offset as u8 & 0x7F)
+        .with_tdcv(config.tdc_value);
+    let nominal = NominalBitTimeConfig::new().with_brp(1).with_tseg1(30).with_tseg2(7).with_sjw(3);
+    let data = DataBitTimeConfig::new().with_brp(0).with_tseg1(12).with_tseg2(5).with_sjw(2);
+    assert_eq!(BitRateConfig::from_registers(nominal, data, tdc), config);
+
+    let info = config.decode(&Clock::Clock40MHz);
+    assert_eq!(info.arbitration, PhaseTiming { bit_rate: 500_000, time_quanta: 40, sample_point: 800, sjw: 4 });
+    assert_eq!(info.data, PhaseTiming { bit_rate: 2_000_000, time_quanta: 20, sample_point: 700, sjw: 3 });
+}
+
+#[test]
+fn test_raw_config_validation() {
+    let config = BitRateConfig { arbitration_tseg2: 3, arbitration_sjw: 4, ..Default::default() };
+    assert!(!config.is_valid());
+    let config = BitRateConfig { data_tseg1: 32, ..Default::default() };
+    assert!(!config.is_valid());
+}
+
+/// Bit rate and sample point in per mille of one phase, worked out from the register fields
+fn phase_timing(clock: u32, brp: u8, tseg1: u8, tseg2: u8) -> (u32, u32) {
+    let before_sample_point = 1 + (tseg1 as u32 + 1);
+    let quanta = before_sample_point + (tseg2 as u32 + 1);
+    (clock / ((brp as u32 + 1) * quanta), 1000 * before_sample_point / quanta)
+}
+
+#[test]
+fn test_bus_profiles() {
+    // Arbitration and data phase bit rate and sample point from SAE J2284-4, SAE J2284-5 and
+    // CiA 601-3
+    let profiles = [
+        (BusProfile::SaeJ2284_4, (500_000, 800), (2_000_000, 750)),
+        (BusProfile::SaeJ2284_5, (500_000, 800), (5_000_000, 750)),
+        (BusProfile::CiA601_3_2M, (500_000, 800), (2_000_000, 700)),
+        (BusProfile::CiA601_3_5M, (500_000, 800), (5_000_000, 750)),
+    ];
+    for (profile, arbitration, data) in profiles {
+        for clock in [Clock::Clock20MHz, Clock::Clock40MHz, Clock::Clock20MHzPLL, Clock::Clock40MHzPLL] {
+            let Some(config) = profile.get_config(&clock) else {
+                assert_eq!((&profile, clock.frequency()), (&BusProfile::SaeJ2284_4, 20_000_000));
+                continue;
+            };
+            assert!(config.is_valid());
+            let frequency = clock.frequency();
+            assert_eq!(
+                phase_timing(frequency, config.arbitration_brp, config.arbitration_tseg1, config.arbitration_tseg2),
+                arbitration,
+            );
+            assert_eq!(phase_timing(frequency, config.data_brp, config.data_tseg1, config.data_tseg2), data);
+
+            // Both phases use the shortest time quantum, with SJW as long as phase segment 2
+            assert_eq!((config.arbitration_brp, config.data_brp), (0, 0));
+            assert_eq!(config.arbitration_sjw, config.arbitration_tseg2);
+            assert_eq!(config.data_sjw, config.data_tseg2);
+            // Secondary sample point at the data phase sample point, with TDCO = DTSEG1 + 1
+            assert_eq!(config.tdc_mode, DelayCompensationMode::Auto1);
+            assert_eq!(config.tdc_offset, config.data_tseg1 as i8 + 1);
+        }
+    }
+}
+
+#[test]
+fn test_bus_profile_registers() {
+    let config = BusProfile::SaeJ2284_4.get_config(&Clock::Clock40MHz).unwrap();
+    assert_eq!(config, BitRateConfig {
+        arbitration_brp: 0,
+        arbitration_tseg1: 62,
+        arbitration_tseg2: 15,
+        arbitration_sjw: 15,
+        data_brp: 0,
+        data_tseg1: 13,
+        data_tseg2: 4,
+        data_sjw: 4,
+        tdc_offset: 14,
+        tdc_value: 0,
+        tdc_mode: DelayCompensationMode::Auto1,
+    });
+}
+
+#[test]
+fn test_manual_delay_compensation() {
+    let mut config = Config {
+        bit_timing: BitRate { arbitration: ArbitrationBitRate::Rate500K, data: DataBitRate::Rate5M }.into(),
+        delay_compensation: Some(DelayCompensationConfig::manual(6, 5)),
+        ..Default::default()
+    };
+    let bit_rate_config = config.bit_rate_config().unwrap();
+    assert_eq!(bit_rate_config.tdc_mode, DelayCompensationMode::Manual);
+    assert_eq!((bit_rate_config.tdc_value, bit_rate_config.tdc_offset), (6, 5));
+
+    // A 5 Mbit/s data bit is only 8 system clocks long at 40 MHz
+    config.delay_compensation = Some(DelayCompensationConfig::manual(6, 8));
+    assert!(config.bit_rate_config().is_none());
+    // Secondary sample point before the start of the bit
+    config.delay_compensation = Some(DelayCompensationConfig::manual(2, -4));
+    assert!(config.bit_rate_config().is_none());
+    config.delay_compensation = Some(DelayCompensationConfig::disabled());
+    assert!(config.bit_rate_config().is_some());
+}
+
+#[test]
+fn test_presets() {
+    assert_eq!(BitRate::presets().count(), 44);
+    for bit_rate in BitRate::presets() {
+        if let Some(config) = bit_rate.get_config(&Clock::Clock40MHz) {
+            assert!(config.is_valid());
+        }
+    }
+}
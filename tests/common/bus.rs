@@ -0,0 +1,173 @@
+//! Virtual CAN bus connecting several simulated controllers
+//!
+//! The bus carries one frame per step. Every SPI transaction of any node advances it by a step,
+//! so drivers polling for an outcome keep the bus moving. Pending frames of all nodes arbitrate
+//! by identifier, and the frame is acknowledged if any other node receives it at the same bit
+//! rates. Faults can be injected for the next frames.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use embedded_can::Id;
+use mcp25xxfd::registers::OperationMode;
+use super::simulator::*;
+
+/// Fault affecting one frame on the bus
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// None of the receivers acknowledges the frame
+    MissingAcknowledge,
+    /// A receiver detects a CRC error and destroys the frame with an error frame
+    ErrorFrame,
+}
+
+#[derive(Default)]
+struct BusState {
+    nodes: Vec<Rc<RefCell<Controller>>>,
+    /// Faults for the next frames, in order
+    faults: VecDeque<Fault>,
+    /// Frames that went through, with the node that sent them
+    log: Vec<(usize, BusFrame)>,
+    held: bool,
+}
+
+/// Handle to a virtual bus, shared by the simulators of its nodes
+#[derive(Clone, Default)]
+pub struct VirtualBus(Rc<RefCell<BusState>>);
+
+impl VirtualBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect a new controller, numbered in the order nodes are added
+    pub fn add_node(&self) -> Simulator {
+        let (simulator, controller) = Simulator::on_bus(self.clone());
+        self.0.borrow_mut().nodes.push(controller);
+        simulator
+    }
+
+    /// Apply `fault` to each of the next `frames` frames, after any faults injected before
+    pub fn inject(&self, fault: Fault, frames: usize) {
+        self.0.borrow_mut().faults.extend(std::iter::repeat_n(fault, frames));
+    }
+
+    /// Send `node` into bus-off as if its transmit error counter overflowed
+    pub fn force_bus_off(&self, node: usize) {
+        let state = self.0.borrow();
+        let mut controller = state.nodes[node].borrow_mut();
+        controller.enter_bus_off();
+        controller.refresh();
+    }
+
+    /// Stop SPI transactions from advancing the bus, so that several nodes can queue frames
+    /// before they arbitrate. [`step`](Self::step) still advances it.
+    pub fn hold(&self, held: bool) {
+        self.0.borrow_mut().held = held;
+    }
+
+    /// Frames that went through since the last call, with the node that sent them
+    pub fn take_log(&self) -> Vec<(usize, BusFrame)> {
+        std::mem::take(&mut self.0.borrow_mut().log)
+    }
+
+    /// Advance the bus after an SPI transaction, unless it is held
+    pub(super) fn advance(&self) {
+        if !self.0.borrow().held {
+            self.step();
+        }
+    }
+
+    /// Carry one frame from the node winning arbitration to the others
+    pub fn step(&self) {
+        let mut state = self.0.borrow_mut();
+        let pending: Vec<(usize, u8, BusFrame)> = state.nodes.iter().enumerate()
+            .filter_map(|(node, controller)| {
+                let controller = controller.borrow();
+                if !controller.transmitting() {
+                    return None;
+                }
+                controller.pending_frame().map(|(fifo, frame)| (node, fifo, frame))
+            })
+            .collect();
+
+        // Identical identifiers from different nodes would end in bit errors, the lowest numbered
+        // node wins here instead
+        let winner = pending.iter().min_by_key(|(node, _, frame)| (arbitration_field(&frame.id, frame.rtr), *node));
+        if let Some((sender, fifo, frame)) = winner.cloned() {
+            for (node, fifo, _) in pending.iter().filter(|(node, ..)| *node != sender) {
+                state.nodes[*node].borrow_mut().lost_arbitration(*fifo);
+            }
+            let fault = state.faults.pop_front();
+            if carry(&state.nodes, sender, fifo, &frame, fault) {
+                state.log.push((sender, frame));
+            }
+        }
+        for controller in &state.nodes {
+            controller.borrow_mut().bus_idle();
+        }
+    }
+}
+
+/// Bits of the arbitration field, compared in order with the dominant `false` winning: base
+/// identifier, RTR or SRR, IDE, identifier extension and RTR of extended frames
+fn arbitration_field(id: &Id, rtr: bool) -> (u16, bool, bool, u32, bool) {
+    match id {
+        Id::Standard(id) => (id.as_raw(), rtr, false, 0, false),
+        Id::Extended(id) => ((id.as_raw() >> 18) as u16, true, true, id.as_raw() & 0x3FFFF, rtr),
+    }
+}
+
+/// Send `frame` from `sender` to the other nodes, returning whether it went through
+fn carry(nodes: &[Rc<RefCell<Controller>>], sender: usize, fifo: u8, frame: &BusFrame, fault: Option<Fault>) -> bool {
+    let (bit_times, classic) = {
+        let transmitter = nodes[sender].borrow();
+        (transmitter.bit_times(), transmitter.mode() == OperationMode::Classic)
+    };
+    if classic && frame.fd {
+        nodes[sender].borrow_mut().transmit_failed(fifo, BDIAG1_NFORMERR);
+        return false;
+    }
+    let receivers: Vec<_> = nodes.iter().enumerate()
+        .filter(|(node, controller)| *node != sender && controller.borrow().listening())
+        .map(|(_, controller)| controller)
+        .collect();
+
+    // Receivers that can't follow the frame destroy it while they are error active
+    let destroyed = receivers.iter().any(|controller| {
+        let controller = controller.borrow();
+        !controller.understands(frame, bit_times) && controller.sends_error_frames()
+    });
+    if fault == Some(Fault::ErrorFrame) || destroyed {
+        // The error flag overwrites the transmitter's end of frame field
+        nodes[sender].borrow_mut().transmit_failed(fifo, BDIAG1_NFORMERR);
+        let receive_error = match fault {
+            Some(Fault::ErrorFrame) if frame.brs => BDIAG1_DCRCERR,
+            Some(Fault::ErrorFrame) => BDIAG1_NCRCERR,
+            _ => BDIAG1_NFORMERR,
+        };
+        for controller in &receivers {
+            controller.borrow_mut().receive_failed(receive_error);
+        }
+        return false;
+    }
+
+    let acknowledged = fault != Some(Fault::MissingAcknowledge) && receivers.iter()
+        .any(|controller| controller.borrow().acknowledges() && controller.borrow().understands(frame, bit_times));
+    if !acknowledged {
+        // The transmitter's error frame follows the missing acknowledgement, so nobody keeps it
+        nodes[sender].borrow_mut().transmit_failed(fifo, BDIAG1_NACKERR);
+        return false;
+    }
+
+    nodes[sender].borrow_mut().transmit_succeeded(fifo, frame);
+    for controller in receivers {
+        let mut controller = controller.borrow_mut();
+        if controller.understands(frame, bit_times) {
+            controller.received(frame);
+        } else {
+            controller.receive_failed(BDIAG1_NFORMERR);
+        }
+    }
+    true
+}
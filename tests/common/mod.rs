@@ -0,0 +1,72 @@
+#![allow(dead_code)]
+
+pub mod bus;
+pub mod simulator;
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use embedded_can::StandardId;
+use mcp25xxfd::config::{Config, FIFOConfig, FilterConfig, MaskConfig};
+use mcp25xxfd::interrupt::InterruptPins;
+use mcp25xxfd::registers::{OperationMode, PayloadSize};
+use mcp25xxfd::MCP25xxFD;
+use simulator::Simulator;
+
+pub const TX_FIFO: u8 = 1;
+pub const RX_FIFO: u8 = 2;
+
+/// Run a driver future to completion. The SPI devices of the tests never make it wait, so there's
+/// no need for a real executor.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// How a test sets up its controller: `config`, TX FIFO 1, RX FIFO 2 taking every frame through
+/// filter 0, and then `mode`
+pub struct NodeSetup {
+    pub config: Config,
+    pub tx_fifo: FIFOConfig<TX_FIFO>,
+    pub rx_fifo: FIFOConfig<RX_FIFO>,
+    pub mode: OperationMode,
+}
+
+impl Default for NodeSetup {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+            tx_fifo: FIFOConfig::tx_with_size(4, PayloadSize::Bytes64),
+            rx_fifo: FIFOConfig::rx_with_size(4, PayloadSize::Bytes64),
+            mode: OperationMode::Normal,
+        }
+    }
+}
+
+impl NodeSetup {
+    /// Reset the controller behind `driver` and set it up
+    pub fn apply<INT: InterruptPins>(self, driver: &mut MCP25xxFD<Simulator, INT>) {
+        block_on(async {
+            driver.reset_and_apply_config(&self.config).await.unwrap();
+            driver.configure_fifo(self.tx_fifo).await.unwrap();
+            driver.configure_fifo(self.rx_fifo).await.unwrap();
+            driver.configure_filter(
+                FilterConfig::<0, RX_FIFO>::from_id(StandardId::ZERO),
+                MaskConfig::<0>::match_anything(),
+            ).await.unwrap();
+            driver.set_mode(self.mode).await.unwrap();
+        });
+    }
+
+    /// A driver without interrupt pins for `simulator`, set up
+    pub fn driver(self, simulator: &Simulator) -> MCP25xxFD<Simulator> {
+        let mut driver = MCP25xxFD::new(simulator.clone());
+        self.apply(&mut driver);
+        driver
+    }
+}
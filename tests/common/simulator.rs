@@ -0,0 +1,1327 @@
+//! Behavioral model of an MCP2518FD behind an SPI device, for running the driver on the host
+//!
+//! The model decodes the SPI instructions and keeps the SFRs and the 2 KB message RAM. After every
+//! SPI transaction the CAN controller takes one step, sending the highest priority pending frame.
+//! Frames other nodes put on the bus go through the filters as they arrive. Arbitration between
+//! nodes, bit rate agreement and error frames are left to [`VirtualBus`]. ECC isn't modeled.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::future::poll_fn;
+use std::rc::Rc;
+use std::task::Poll;
+use embedded_can::{ExtendedId, Id, StandardId};
+use embedded_hal::spi::{ErrorType, Operation};
+use embedded_hal_async::digital::Wait;
+use mcp25xxfd::crc::crc16;
+use mcp25xxfd::registers::*;
+use mcp25xxfd::Instruction;
+use super::bus::VirtualBus;
+
+const RAM_START: u16 = 0x400;
+const RAM_END: u16 = RAM_START + 2048;
+const CAN_END: u16 = 0x2F0;
+const DEVICE_START: u16 = 0xE00;
+const DEVICE_END: u16 = 0xE18;
+
+const RESET: u8 = Instruction::Reset as u8;
+const READ: u8 = Instruction::Read as u8;
+const WRITE: u8 = Instruction::Write as u8;
+const READ_CRC: u8 = Instruction::ReadCRC as u8;
+const WRITE_CRC: u8 = Instruction::WriteCRC as u8;
+const WRITE_SAFE: u8 = Instruction::WriteSafe as u8;
+
+// Flag bits of CiINT that stay set until cleared over SPI
+const INT_TBCIF: u32 = 1 << 2;
+const INT_MODIF: u32 = 1 << 3;
+const INT_SERRIF: u32 = 1 << 12;
+const INT_CERRIF: u32 = 1 << 13;
+const INT_WAKIF: u32 = 1 << 14;
+const INT_IVMIF: u32 = 1 << 15;
+// Flag bits of CiINT that follow other registers
+const INT_TXIF: u32 = 1 << 0;
+const INT_RXIF: u32 = 1 << 1;
+const INT_TEFIF: u32 = 1 << 4;
+const INT_SPICRCIF: u32 = 1 << 9;
+const INT_TXATIF: u32 = 1 << 10;
+const INT_RXOVIF: u32 = 1 << 11;
+const INT_WAKIE: u32 = INT_WAKIF << 16;
+
+// First byte of OSC
+const OSC_LPMEN: u8 = 1 << 3;
+
+// First byte of CiFIFOSTAm, CiTXQSTA and CiTEFSTA
+const STATUS_RXOVIF: u8 = 1 << 3;
+const STATUS_TXATIF: u8 = 1 << 4;
+const STATUS_TXERR: u8 = 1 << 5;
+const STATUS_TXLARB: u8 = 1 << 6;
+const STATUS_TXABT: u8 = 1 << 7;
+const STATUS_TEFOVIF: u8 = 1 << 3;
+// Second byte of CiFIFOCONm, CiTXQCON and CiTEFCON
+const CONTROL_UINC: u8 = 1 << 0;
+const CONTROL_TXREQ: u8 = 1 << 1;
+const CONTROL_FRESET: u8 = 1 << 2;
+
+// CiBDIAG1
+pub(super) const BDIAG1_NACKERR: u32 = 1 << 18;
+pub(super) const BDIAG1_NFORMERR: u32 = 1 << 19;
+pub(super) const BDIAG1_NCRCERR: u32 = 1 << 21;
+const BDIAG1_TXBOERR: u32 = 1 << 23;
+pub(super) const BDIAG1_DCRCERR: u32 = 1 << 29;
+const BDIAG1_DLCMM: u32 = 1 << 31;
+
+/// Frame times a controller in bus-off waits before it recovers, standing in for 128 occurrences
+/// of 11 recessive bits
+const BUS_OFF_RECOVERY: u16 = 128;
+
+const fn fifo_control_address(fifo: u8) -> u16 {
+    0x050 + 12 * fifo as u16
+}
+const fn fifo_status_address(fifo: u8) -> u16 {
+    fifo_control_address(fifo) + 4
+}
+const fn fifo_user_address(fifo: u8) -> u16 {
+    fifo_control_address(fifo) + 8
+}
+
+/// A frame on the simulated bus
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BusFrame {
+    pub id: Id,
+    pub dlc: DataLengthCode,
+    /// As many bytes as the DLC stands for
+    pub data: Vec<u8>,
+    pub fd: bool,
+    pub brs: bool,
+    pub rtr: bool,
+    pub esi: bool,
+}
+impl BusFrame {
+    /// A data frame, in FD format with bit rate switching if it carries more than 8 bytes
+    ///
+    /// The data is padded with zeros up to the next valid length.
+    pub fn new(id: impl Into<Id>, data: &[u8]) -> Self {
+        let dlc = DataLengthCode::best_fit(data.len()).expect("Too much data for a CAN FD frame");
+        let mut data = data.to_vec();
+        data.resize(dlc.bytes(), 0);
+        let fd = dlc.bytes() > 8;
+        Self { id: id.into(), dlc, data, fd, brs: fd, rtr: false, esi: false }
+    }
+
+    /// SID and EID fields of a message object, and whether the identifier is extended
+    fn id_fields(&self) -> (u16, u32, bool) {
+        match self.id {
+            Id::Standard(id) => (id.as_raw(), 0, false),
+            Id::Extended(id) => ((id.as_raw() >> 18) as u16, id.as_raw() & 0x3FFFF, true),
+        }
+    }
+
+    fn from_transmit_object(header: TransmitMessageObjectHeader, data: &[u8]) -> Self {
+        let id = if header.ide() {
+            ExtendedId::new(((header.sid() as u32) << 18) | header.eid()).unwrap().into()
+        } else {
+            StandardId::new(header.sid()).unwrap().into()
+        };
+        let mut data = data.to_vec();
+        data.resize(header.dlc().bytes(), 0);
+        Self {
+            id,
+            dlc: header.dlc(),
+            data,
+            fd: header.fdf(),
+            brs: header.brs(),
+            rtr: header.rtr(),
+            esi: header.esi(),
+        }
+    }
+}
+
+/// Head, tail and fill level of a FIFO, which the controller keeps internally
+#[derive(Copy, Clone, Debug, Default)]
+struct FIFOState {
+    head: u8,
+    tail: u8,
+    count: u8,
+    /// Attempts made to send the message at the tail
+    attempts: u8,
+}
+
+/// Where a FIFO sits in message RAM
+#[derive(Copy, Clone, Debug)]
+struct FIFOLayout {
+    /// Offset from the start of RAM
+    start: u16,
+    object_size: u16,
+    size: u8,
+}
+impl FIFOLayout {
+    fn object_address(&self, object: u8) -> u16 {
+        self.start + object as u16 * self.object_size
+    }
+}
+
+/// Bytes of the SPI transaction in progress
+#[derive(Default)]
+struct SPITransaction {
+    mosi: Vec<u8>,
+    /// Data sent back by a `ReadCRC`, for the CRC that follows it
+    miso: Vec<u8>,
+    /// Whether a data bit was flipped on the way
+    corrupted: bool,
+}
+impl SPITransaction {
+    fn instruction(&self) -> u8 {
+        self.mosi[0] >> 4
+    }
+    fn address(&self) -> u16 {
+        ((self.mosi[0] as u16 & 0xF) << 8) | self.mosi[1] as u16
+    }
+}
+
+/// Data bytes following a `ReadCRC` or `WriteCRC` with length byte `n`, which counts bytes for SFRs
+/// and words for RAM
+fn crc_data_length(address: u16, n: u8) -> usize {
+    if (RAM_START..RAM_END).contains(&address) { 4 * n as usize } else { n as usize }
+}
+
+pub(crate) struct Controller {
+    can: [u8; CAN_END as usize],
+    device: [u8; (DEVICE_END - DEVICE_START) as usize],
+    ram: [u8; (RAM_END - RAM_START) as usize],
+    /// TXQ and FIFOs 1 to 31
+    fifos: [FIFOState; 32],
+    tef: FIFOState,
+    /// Frames sent by this controller that haven't been taken by the test yet
+    transmitted: VecDeque<BusFrame>,
+    /// Whether another node acknowledges the frames this controller sends, unless it is on a
+    /// virtual bus
+    acknowledge: bool,
+    /// Whether the controller is a node on a virtual bus
+    on_bus: bool,
+    /// Frame times until the controller recovers from bus-off
+    bus_off: Option<u16>,
+    /// CRC-protected transactions left to corrupt
+    corruptions: usize,
+    /// SPI transactions since the controller was created
+    transactions: usize,
+}
+
+impl Controller {
+    fn new() -> Self {
+        let mut controller = Self {
+            can: [0; CAN_END as usize],
+            device: [0; (DEVICE_END - DEVICE_START) as usize],
+            ram: [0; (RAM_END - RAM_START) as usize],
+            fifos: [FIFOState::default(); 32],
+            tef: FIFOState::default(),
+            transmitted: VecDeque::new(),
+            acknowledge: true,
+            on_bus: false,
+            bus_off: None,
+            corruptions: 0,
+            transactions: 0,
+        };
+        controller.reset();
+        controller
+    }
+
+    /// Reset values from the datasheet. Message RAM keeps its contents.
+    fn reset(&mut self) {
+        self.can = [0; CAN_END as usize];
+        self.device = [0; (DEVICE_END - DEVICE_START) as usize];
+        self.fifos = [FIFOState::default(); 32];
+        self.tef = FIFOState::default();
+        self.bus_off = None;
+
+        self.set_word(CANControl::ADDRESS, 0x0498_0760);
+        self.set_word(NominalBitTimeConfig::ADDRESS, 0x003E_0F0F);
+        self.set_word(DataBitTimeConfig::ADDRESS, 0x000E_0303);
+        self.set_word(TransmitterDelayCompensation::ADDRESS, 0x0002_1000);
+        self.set_word(TransmitQueueControl::ADDRESS, 0x0060_0080);
+        for fifo in 1..32 {
+            self.set_word(fifo_control_address(fifo), 0x0060_0000);
+        }
+        self.set_word(OscillatorControl::ADDRESS, 0x0000_0460);
+        self.set_word(IOControl::ADDRESS, 0x0000_0003);
+        self.set_word(DeviceID::ADDRESS, 0x0000_0014);
+        self.refresh();
+    }
+
+    fn word(&self, address: u16) -> u32 {
+        u32::from_le_bytes(self.bytes(address))
+    }
+    fn bytes(&self, address: u16) -> [u8; 4] {
+        core::array::from_fn(|i| self.read_byte(address + i as u16))
+    }
+    fn set_word(&mut self, address: u16, value: u32) {
+        let bytes = value.to_le_bytes();
+        match address {
+            0..CAN_END => self.can[address as usize..][..4].copy_from_slice(&bytes),
+            DEVICE_START..DEVICE_END => self.device[(address - DEVICE_START) as usize..][..4].copy_from_slice(&bytes),
+            _ => panic!("No SFR at {address:#05x}"),
+        }
+    }
+
+    fn can_control(&self) -> CANControl {
+        CANControl::from_bytes(self.bytes(CANControl::ADDRESS))
+    }
+    pub(super) fn mode(&self) -> OperationMode {
+        self.can_control().opmode()
+    }
+    fn fifo_control(&self, fifo: u8) -> FIFOControlM {
+        FIFOControlM::from_bytes(self.bytes(fifo_control_address(fifo)))
+    }
+    /// Whether `fifo` is the TXQ or a TX FIFO
+    fn is_transmit(&self, fifo: u8) -> bool {
+        fifo == 0 || self.fifo_control(fifo).txen()
+    }
+
+    fn tef_layout(&self) -> Option<FIFOLayout> {
+        if !self.can_control().stef() {
+            return None;
+        }
+        let control = TransmitEventFIFOControl::from_bytes(self.bytes(TransmitEventFIFOControl::ADDRESS));
+        let object_size = 8 + if control.teftsen() { 4 } else { 0 };
+        Some(FIFOLayout { start: 0, object_size, size: control.fsize() + 1 })
+    }
+
+    /// RAM layout of the TXQ (0) or FIFO `fifo`: the TEF comes first, then the TXQ and FIFOs 1 to 31
+    fn fifo_layout(&self, fifo: u8) -> Option<FIFOLayout> {
+        let can_control = self.can_control();
+        if fifo == 0 && !can_control.txqen() {
+            return None;
+        }
+        let mut start = self.tef_layout().map_or(0, |tef| tef.size as u16 * tef.object_size);
+        for m in 0..32 {
+            if m == 0 && !can_control.txqen() {
+                continue;
+            }
+            let control = self.fifo_control(m);
+            let timestamp = m != 0 && !control.txen() && control.rxtsen();
+            let object_size = 8 + if timestamp { 4 } else { 0 } + control.plsize().bytes() as u16;
+            let layout = FIFOLayout { start, object_size, size: control.fsize() + 1 };
+            if m == fifo {
+                return Some(layout);
+            }
+            start += layout.size as u16 * object_size;
+        }
+        None
+    }
+
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) {
+        let mut spi = SPITransaction::default();
+        for operation in operations {
+            match operation {
+                Operation::Read(read) => {
+                    for byte in read.iter_mut() {
+                        *byte = self.clock(&mut spi, 0);
+                    }
+                },
+                Operation::Write(write) => {
+                    for byte in write.iter() {
+                        self.clock(&mut spi, *byte);
+                    }
+                },
+                Operation::Transfer(read, write) => {
+                    for i in 0..read.len().max(write.len()) {
+                        let miso = self.clock(&mut spi, write.get(i).copied().unwrap_or(0));
+                        if let Some(byte) = read.get_mut(i) {
+                            *byte = miso;
+                        }
+                    }
+                },
+                Operation::TransferInPlace(words) => {
+                    for byte in words.iter_mut() {
+                        *byte = self.clock(&mut spi, *byte);
+                    }
+                },
+                Operation::DelayNs(_) => {},
+            }
+        }
+        self.end_transaction(&spi);
+        self.step();
+    }
+
+    /// Shift one byte in and one byte out
+    fn clock(&mut self, spi: &mut SPITransaction, mosi: u8) -> u8 {
+        spi.mosi.push(mosi);
+        let position = spi.mosi.len() - 1;
+        if position < 2 {
+            return 0;
+        }
+        let address = spi.address();
+        // Noise flips the lowest bit of the first data byte, after the CRC of a read was worked out
+        let first_data = match spi.instruction() {
+            WRITE_SAFE => 2,
+            READ_CRC | WRITE_CRC => 3,
+            _ => usize::MAX,
+        };
+        let corrupt = position == first_data && self.corruptions > 0;
+        if corrupt {
+            spi.corrupted = true;
+            if spi.instruction() != READ_CRC {
+                spi.mosi[position] ^= 1;
+            }
+        }
+        let miso = self.shift(spi, address, position, mosi);
+        if corrupt && spi.instruction() == READ_CRC { miso ^ 1 } else { miso }
+    }
+
+    /// Carry out the byte at `position` of an instruction at `address`, returning the byte sent back
+    fn shift(&mut self, spi: &mut SPITransaction, address: u16, position: usize, mosi: u8) -> u8 {
+        match spi.instruction() {
+            READ => self.read_byte(address.wrapping_add(position as u16 - 2)),
+            WRITE => {
+                self.write_byte(address.wrapping_add(position as u16 - 2), mosi);
+                0
+            },
+            READ_CRC if position > 2 => {
+                let length = crc_data_length(address, spi.mosi[2]);
+                let index = position - 3;
+                if index < length {
+                    let byte = self.read_byte(address.wrapping_add(index as u16));
+                    spi.miso.push(byte);
+                    return byte;
+                }
+                let mut message = spi.mosi[..3].to_vec();
+                message.extend(&spi.miso);
+                crc16(&message).to_be_bytes().get(index - length).copied().unwrap_or(0)
+            },
+            // WriteCRC and WriteSafe are carried out once the CRC is in
+            _ => 0,
+        }
+    }
+
+    fn end_transaction(&mut self, spi: &SPITransaction) {
+        self.transactions += 1;
+        if spi.corrupted {
+            self.corruptions -= 1;
+        }
+        if spi.mosi.len() < 2 {
+            return;
+        }
+        let address = spi.address();
+        match spi.instruction() {
+            RESET => self.reset(),
+            WRITE_CRC => {
+                let length = spi.mosi.get(2).map(|n| crc_data_length(address, *n));
+                match length {
+                    Some(length) if spi.mosi.len() == 3 + length + 2 => {
+                        self.checked_write(address, &spi.mosi[..3 + length], 3, &spi.mosi[3 + length..]);
+                    },
+                    _ => self.spi_crc_error(false, 0),
+                }
+            },
+            WRITE_SAFE => {
+                let data_length = spi.mosi.len().saturating_sub(4);
+                let ram = (RAM_START..RAM_END).contains(&address);
+                if (ram && data_length == 4) || (!ram && (1..=4).contains(&data_length)) {
+                    let end = spi.mosi.len() - 2;
+                    self.checked_write(address, &spi.mosi[..end], 2, &spi.mosi[end..]);
+                }
+                else {
+                    self.spi_crc_error(false, 0);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Write the data at `data_start` in `message` if the CRC over `message` matches
+    fn checked_write(&mut self, address: u16, message: &[u8], data_start: usize, crc: &[u8]) {
+        let expected = crc16(message);
+        if expected.to_be_bytes() != crc {
+            self.spi_crc_error(true, expected);
+            return;
+        }
+        for (i, byte) in message[data_start..].iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u16), *byte);
+        }
+    }
+
+    fn spi_crc_error(&mut self, crc_error: bool, crc: u16) {
+        let status = CRCStatus::ADDRESS - DEVICE_START;
+        if crc_error {
+            self.device[status as usize..][..2].copy_from_slice(&crc.to_le_bytes());
+            self.device[status as usize + 2] |= 1 << 0;
+        }
+        else {
+            self.device[status as usize + 2] |= 1 << 1;
+        }
+        self.refresh();
+    }
+
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0..CAN_END => self.can[address as usize],
+            RAM_START..RAM_END => self.ram[(address - RAM_START) as usize],
+            DEVICE_START..DEVICE_END => self.device[(address - DEVICE_START) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0..CAN_END => self.write_can(address, value),
+            RAM_START..RAM_END => {
+                self.ram[(address - RAM_START) as usize] = value;
+                return;
+            },
+            DEVICE_START..DEVICE_END => self.write_device(address, value),
+            _ => {},
+        }
+        self.refresh();
+    }
+
+    /// Write one byte, keeping the bits outside `writable`
+    fn store(&mut self, address: u16, value: u8, writable: u8) {
+        let byte = &mut self.can[address as usize];
+        *byte = (*byte & !writable) | (value & writable);
+    }
+
+    /// Clear the flags in `flags` written as 0
+    fn clear_flags(&mut self, address: u16, value: u8, flags: u8) {
+        self.can[address as usize] &= value | !flags;
+    }
+
+    fn write_can(&mut self, address: u16, value: u8) {
+        let register = address & !3;
+        let byte = address & 3;
+        let configuration = self.mode() == OperationMode::Configuration;
+        match register {
+            0x000 => self.write_can_control(byte, value, configuration),
+            // Bit timing can only be changed in Configuration mode
+            0x004 | 0x008 if configuration => self.store(address, value, 0xFF),
+            // TDC, TBC, TSCON, BDIAG0 and BDIAG1
+            0x00C | 0x010 | 0x014 | 0x038 | 0x03C => self.store(address, value, 0xFF),
+            0x01C => match byte {
+                0 => self.clear_flags(address, value, (INT_TBCIF | INT_MODIF) as u8),
+                1 => self.clear_flags(address, value, ((INT_SERRIF | INT_CERRIF | INT_WAKIF | INT_IVMIF) >> 8) as u8),
+                _ => self.store(address, value, 0xFF),
+            },
+            0x030 => {
+                for bit in 0..8 {
+                    if value & (1 << bit) != 0 {
+                        self.request_transmission(byte as u8 * 8 + bit);
+                    }
+                }
+            },
+            0x040 => self.write_tef_control(byte, value, configuration),
+            0x044 if byte == 0 => self.clear_flags(address, value, STATUS_TEFOVIF),
+            0x050..0x1D0 => {
+                let fifo = ((register - 0x050) / 12) as u8;
+                match (register - 0x050) % 12 {
+                    0 => self.write_fifo_control(fifo, byte, value, configuration),
+                    4 if byte == 0 => self.clear_flags(address, value, STATUS_RXOVIF | STATUS_TXATIF | STATUS_TXERR | STATUS_TXLARB | STATUS_TXABT),
+                    _ => {},
+                }
+            },
+            // Filter control, filter objects and masks
+            0x1D0.. => self.store(address, value, 0xFF),
+            // Status registers
+            _ => {},
+        }
+    }
+
+    fn write_can_control(&mut self, byte: u16, value: u8, configuration: bool) {
+        match byte {
+            0 => self.store(byte, value, 0xFF),
+            // BUSY is read only
+            1 => self.store(byte, value, !0x08),
+            // OPMODE is read only, STEF and TXQEN change the RAM layout so need Configuration mode
+            2 => self.store(byte, value, if configuration { 0x1F } else { 0x07 }),
+            _ => {
+                self.store(byte, value, 0xFF);
+                self.request_mode(self.can_control().reqop());
+                if self.can_control().abat() {
+                    for fifo in 0..32 {
+                        self.abort(fifo);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Switch modes straight away. Loopback modes can only be entered from and left to
+    /// Configuration mode.
+    fn request_mode(&mut self, requested: OperationMode) {
+        use OperationMode::*;
+        let current = self.mode();
+        let loopback = |mode| matches!(mode, InternalLoopback | ExternalLoopback);
+        let allowed = current == Configuration || requested == Configuration || (!loopback(current) && !loopback(requested));
+        if requested == current || !allowed {
+            return;
+        }
+        self.can[2] = (self.can[2] & 0x1F) | (requested as u8) << 5;
+        self.can[Interrupts::ADDRESS as usize] |= INT_MODIF as u8;
+    }
+
+    fn write_tef_control(&mut self, byte: u16, value: u8, configuration: bool) {
+        let address = TransmitEventFIFOControl::ADDRESS + byte;
+        match byte {
+            0 => self.store(address, value, 0x2F),
+            1 => {
+                if value & CONTROL_FRESET != 0 {
+                    self.tef = FIFOState::default();
+                    self.can[TransmitEventFIFOStatus::ADDRESS as usize] = 0;
+                }
+                if value & CONTROL_UINC != 0 && self.tef.count > 0 {
+                    let size = self.tef_layout().map_or(1, |tef| tef.size);
+                    self.tef.tail = (self.tef.tail + 1) % size;
+                    self.tef.count -= 1;
+                }
+            },
+            3 if configuration => self.store(address, value, 0x1F),
+            _ => {},
+        }
+    }
+
+    fn write_fifo_control(&mut self, fifo: u8, byte: u16, value: u8, configuration: bool) {
+        let address = fifo_control_address(fifo) + byte;
+        match byte {
+            // TXEN and RXTSEN change the RAM layout, TXEN of the TXQ is always set
+            0 => self.store(address, value, if configuration && fifo != 0 { 0xFF } else { 0x5F }),
+            1 => {
+                if value & CONTROL_FRESET != 0 {
+                    self.reset_fifo(fifo);
+                }
+                if value & CONTROL_UINC != 0 {
+                    self.increment(fifo);
+                }
+                if value & CONTROL_TXREQ != 0 {
+                    self.request_transmission(fifo);
+                }
+                else {
+                    self.abort(fifo);
+                }
+            },
+            2 => self.store(address, value, 0x7F),
+            _ => if configuration { self.store(address, value, 0xFF) },
+        }
+    }
+
+    fn reset_fifo(&mut self, fifo: u8) {
+        self.fifos[fifo as usize] = FIFOState::default();
+        self.can[fifo_control_address(fifo) as usize + 1] &= !CONTROL_TXREQ;
+        self.can[fifo_status_address(fifo) as usize] = 0;
+    }
+
+    /// `UINC`: a message was written to a TX FIFO or read from an RX FIFO
+    fn increment(&mut self, fifo: u8) {
+        let Some(layout) = self.fifo_layout(fifo) else { return };
+        let transmit = self.is_transmit(fifo);
+        let state = &mut self.fifos[fifo as usize];
+        if transmit && state.count < layout.size {
+            state.head = (state.head + 1) % layout.size;
+            state.count += 1;
+        }
+        else if !transmit && state.count > 0 {
+            state.tail = (state.tail + 1) % layout.size;
+            state.count -= 1;
+        }
+    }
+
+    fn request_transmission(&mut self, fifo: u8) {
+        if fifo >= 32 || !self.is_transmit(fifo) || self.fifo_layout(fifo).is_none() {
+            return;
+        }
+        self.can[fifo_control_address(fifo) as usize + 1] |= CONTROL_TXREQ;
+        self.can[fifo_status_address(fifo) as usize] &= !(STATUS_TXERR | STATUS_TXLARB | STATUS_TXABT);
+        self.fifos[fifo as usize].attempts = 0;
+    }
+
+    /// Clearing `TXREQ` or setting `ABAT` aborts the messages still waiting
+    fn abort(&mut self, fifo: u8) {
+        let control = fifo_control_address(fifo) as usize + 1;
+        if self.can[control] & CONTROL_TXREQ == 0 {
+            return;
+        }
+        self.can[control] &= !CONTROL_TXREQ;
+        if self.fifos[fifo as usize].count > 0 {
+            self.can[fifo_status_address(fifo) as usize] |= STATUS_TXABT;
+        }
+    }
+
+    fn write_device(&mut self, address: u16, value: u8) {
+        let offset = (address - DEVICE_START) as usize;
+        match address & !3 {
+            // Ready flags in the second byte are read only. Any write wakes the controller up.
+            OscillatorControl::ADDRESS if offset == 0 => {
+                if self.mode() == OperationMode::Sleep {
+                    let low_power = self.device[0] & OSC_LPMEN != 0;
+                    self.wake_up();
+                    // The reset on leaving Low Power Mode swallows the write
+                    if low_power {
+                        return;
+                    }
+                }
+                self.device[0] = value & 0x7D;
+            },
+            IOControl::ADDRESS => self.device[offset] = value,
+            CRCStatus::ADDRESS => match offset & 3 {
+                2 => self.device[offset] &= value | !0x03,
+                3 => self.device[offset] = value & 0x03,
+                _ => {},
+            },
+            ECCControl::ADDRESS => self.device[offset] = value,
+            ECCStatus::ADDRESS if offset & 3 == 0 => self.device[offset] &= value | !0x06,
+            _ => {},
+        }
+    }
+
+    /// Run the CAN controller for one step, sending the next frame if there is one
+    ///
+    /// On a [`VirtualBus`](super::bus::VirtualBus) the bus decides which node gets to send, so
+    /// only frames looped back inside the controller are sent here.
+    fn step(&mut self) {
+        use OperationMode::*;
+        let mode = self.mode();
+        self.advance_time(1);
+        let loopback = matches!(mode, InternalLoopback | ExternalLoopback);
+        if loopback || !self.on_bus {
+            self.transmit_next(mode);
+        }
+        self.refresh();
+    }
+
+    /// A frame from another node appears on the bus
+    fn bus_frame(&mut self, frame: &BusFrame) {
+        if self.listening() {
+            self.received(frame);
+            self.refresh();
+        }
+        else if self.mode() == OperationMode::Sleep && self.word(Interrupts::ADDRESS) & INT_WAKIE != 0 {
+            // The frame itself is lost
+            self.wake_up();
+            self.can[Interrupts::ADDRESS as usize + 1] |= (INT_WAKIF >> 8) as u8;
+            self.refresh();
+        }
+    }
+
+    /// Leave Sleep mode for Configuration mode. Leaving Low Power Mode resets the controller.
+    fn wake_up(&mut self) {
+        if self.device[0] & OSC_LPMEN != 0 {
+            self.reset();
+            return;
+        }
+        self.can[2] = (self.can[2] & 0x1F) | (OperationMode::Configuration as u8) << 5;
+        self.can[Interrupts::ADDRESS as usize] |= INT_MODIF as u8;
+    }
+
+    fn advance_time(&mut self, ticks: u32) {
+        let time_stamp_control = TimeStampControl::from_bytes(self.bytes(TimeStampControl::ADDRESS));
+        if time_stamp_control.tbcen() {
+            let counter = self.word(TimeBaseCounter::ADDRESS);
+            self.set_word(TimeBaseCounter::ADDRESS, counter.wrapping_add(ticks));
+        }
+    }
+
+    /// The TX FIFO with the highest priority that has a message waiting, the lowest numbered one
+    /// among equals
+    fn next_transmit_fifo(&self) -> Option<u8> {
+        let mut next: Option<(u8, u8)> = None;
+        for fifo in 0..32 {
+            let control = self.fifo_control(fifo);
+            let waiting = self.is_transmit(fifo) && self.fifo_layout(fifo).is_some()
+                && control.txreq() && self.fifos[fifo as usize].count > 0;
+            if waiting && next.is_none_or(|(_, priority)| control.txpri() > priority) {
+                next = Some((fifo, control.txpri()));
+            }
+        }
+        next.map(|(fifo, _)| fifo)
+    }
+
+    /// The frame the controller would put on the bus next, and its FIFO
+    pub(super) fn pending_frame(&self) -> Option<(u8, BusFrame)> {
+        if self.bus_off.is_some() {
+            return None;
+        }
+        let fifo = self.next_transmit_fifo()?;
+        let layout = self.fifo_layout(fifo)?;
+        let control = self.fifo_control(fifo);
+        let address = layout.object_address(self.fifos[fifo as usize].tail) as usize;
+        let header = TransmitMessageObjectHeader::from_bytes(self.ram[address..][..8].try_into().unwrap());
+        let length = header.dlc().bytes().min(control.plsize().bytes());
+        Some((fifo, BusFrame::from_transmit_object(header, &self.ram[address + 8..][..length])))
+    }
+
+    fn transmit_next(&mut self, mode: OperationMode) {
+        if !matches!(mode, OperationMode::Normal | OperationMode::Classic | OperationMode::InternalLoopback | OperationMode::ExternalLoopback) {
+            return;
+        }
+        let Some((fifo, frame)) = self.pending_frame() else { return };
+        if mode == OperationMode::Classic && frame.fd {
+            self.transmit_failed(fifo, BDIAG1_NFORMERR);
+            return;
+        }
+        let loopback = matches!(mode, OperationMode::InternalLoopback | OperationMode::ExternalLoopback);
+        if !loopback && !self.acknowledge {
+            self.transmit_failed(fifo, BDIAG1_NACKERR);
+            return;
+        }
+        self.transmit_succeeded(fifo, &frame);
+        if loopback {
+            self.receive(&frame);
+        }
+    }
+
+    /// The frame at the tail of `fifo` went through
+    pub(super) fn transmit_succeeded(&mut self, fifo: u8, frame: &BusFrame) {
+        let layout = self.fifo_layout(fifo).unwrap();
+        let address = layout.object_address(self.fifos[fifo as usize].tail) as usize;
+        let header: [u8; 8] = self.ram[address..][..8].try_into().unwrap();
+
+        let state = &mut self.fifos[fifo as usize];
+        state.tail = (state.tail + 1) % layout.size;
+        state.count -= 1;
+        state.attempts = 0;
+        if state.count == 0 {
+            self.can[fifo_control_address(fifo) as usize + 1] &= !CONTROL_TXREQ;
+        }
+        self.count_error_free_message();
+        let errors = TransmitReceiveErrorCount::ADDRESS as usize;
+        self.can[errors + 1] = self.can[errors + 1].saturating_sub(1);
+        self.store_transmit_event(header);
+
+        if self.mode() != OperationMode::InternalLoopback {
+            self.transmitted.push_back(frame.clone());
+        }
+    }
+
+    /// The frame at the tail of `fifo` failed with the error in `CiBDIAG1` bit `error`
+    pub(super) fn transmit_failed(&mut self, fifo: u8, error: u32) {
+        self.set_bus_error(error);
+        self.can[fifo_status_address(fifo) as usize] |= STATUS_TXERR;
+        // An error passive transmitter doesn't count missing acknowledgements
+        let errors = TransmitReceiveErrorCount::ADDRESS as usize;
+        let transmit_errors = self.can[errors + 1];
+        if error != BDIAG1_NACKERR || transmit_errors < 128 {
+            match transmit_errors.checked_add(8) {
+                Some(transmit_errors) => self.can[errors + 1] = transmit_errors,
+                None => self.enter_bus_off(),
+            }
+        }
+        self.count_attempt(fifo);
+    }
+
+    /// The frame at the tail of `fifo` lost arbitration to a frame with a higher priority
+    pub(super) fn lost_arbitration(&mut self, fifo: u8) {
+        self.can[fifo_status_address(fifo) as usize] |= STATUS_TXLARB;
+        self.count_attempt(fifo);
+    }
+
+    /// A frame from another node came across the bus intact
+    pub(super) fn received(&mut self, frame: &BusFrame) {
+        self.receive(frame);
+        self.count_error_free_message();
+        let errors = TransmitReceiveErrorCount::ADDRESS as usize;
+        self.can[errors] = self.can[errors].saturating_sub(1);
+    }
+
+    /// A frame from another node was destroyed with the error in `CiBDIAG1` bit `error`
+    pub(super) fn receive_failed(&mut self, error: u32) {
+        self.set_bus_error(error);
+        let errors = TransmitReceiveErrorCount::ADDRESS as usize;
+        self.can[errors] = self.can[errors].saturating_add(1);
+    }
+
+    /// Whether the controller sends frames onto the bus in its current mode
+    pub(super) fn transmitting(&self) -> bool {
+        self.bus_off.is_none() && matches!(self.mode(), OperationMode::Normal | OperationMode::Classic)
+    }
+
+    /// Whether the controller takes part in bus traffic in its current mode
+    pub(super) fn listening(&self) -> bool {
+        use OperationMode::*;
+        self.bus_off.is_none() && matches!(self.mode(), Normal | Classic | ListenOnly | Restricted | ExternalLoopback)
+    }
+
+    /// Whether the controller acknowledges frames it receives correctly
+    pub(super) fn acknowledges(&self) -> bool {
+        self.listening() && matches!(self.mode(), OperationMode::Normal | OperationMode::Classic | OperationMode::Restricted)
+    }
+
+    /// Whether the controller destroys frames it can't receive with an active error frame
+    pub(super) fn sends_error_frames(&self) -> bool {
+        let receive_errors = self.can[TransmitReceiveErrorCount::ADDRESS as usize];
+        self.listening() && matches!(self.mode(), OperationMode::Normal | OperationMode::Classic) && receive_errors < 128
+    }
+
+    /// Whether the controller can receive `frame`: it has to understand FD frames and run at the
+    /// same bit rates as the transmitter
+    pub(super) fn understands(&self, frame: &BusFrame, bit_times: (u32, u32)) -> bool {
+        let (nominal, data) = self.bit_times();
+        let fd_capable = self.mode() != OperationMode::Classic;
+        nominal == bit_times.0 && (!frame.fd || fd_capable) && (!frame.brs || data == bit_times.1)
+    }
+
+    /// Nominal and data bit times, in tenths of an oscillator period
+    pub(super) fn bit_times(&self) -> (u32, u32) {
+        let oscillator = OscillatorControl::from_bytes(self.bytes(OscillatorControl::ADDRESS));
+        let system_clock = if oscillator.pllen() { 1 } else { 10 } * if oscillator.sclkdiv() == ClockDivisor::DivideBy2 { 2 } else { 1 };
+        let nominal = NominalBitTimeConfig::from_bytes(self.bytes(NominalBitTimeConfig::ADDRESS));
+        let data = DataBitTimeConfig::from_bytes(self.bytes(DataBitTimeConfig::ADDRESS));
+        let nominal_clocks = (nominal.brp() as u32 + 1) * (nominal.tseg1() as u32 + nominal.tseg2() as u32 + 3);
+        let data_clocks = (data.brp() as u32 + 1) * (data.tseg1() as u32 + data.tseg2() as u32 + 3);
+        (nominal_clocks * system_clock, data_clocks * system_clock)
+    }
+
+    /// Set the error counters beyond bus-off
+    pub(super) fn enter_bus_off(&mut self) {
+        let errors = TransmitReceiveErrorCount::ADDRESS as usize;
+        self.can[errors + 1] = u8::MAX;
+        self.bus_off = Some(BUS_OFF_RECOVERY);
+        let diagnostic = self.word(BusDiagnostic1::ADDRESS);
+        self.set_word(BusDiagnostic1::ADDRESS, diagnostic | BDIAG1_TXBOERR);
+        self.can[Interrupts::ADDRESS as usize + 1] |= (INT_CERRIF >> 8) as u8;
+    }
+
+    /// One frame time passed on the bus. A controller in bus-off recovers once the bus was idle
+    /// long enough.
+    pub(super) fn bus_idle(&mut self) {
+        if let Some(remaining) = self.bus_off {
+            self.bus_off = remaining.checked_sub(1);
+            if self.bus_off.is_none() {
+                let errors = TransmitReceiveErrorCount::ADDRESS as usize;
+                self.can[errors] = 0;
+                self.can[errors + 1] = 0;
+            }
+        }
+        self.refresh();
+    }
+
+    fn set_bus_error(&mut self, flag: u32) {
+        let diagnostic = self.word(BusDiagnostic1::ADDRESS);
+        self.set_word(BusDiagnostic1::ADDRESS, diagnostic | flag);
+        self.can[Interrupts::ADDRESS as usize + 1] |= (INT_CERRIF >> 8) as u8;
+    }
+
+    fn count_error_free_message(&mut self) {
+        let diagnostic = self.word(BusDiagnostic1::ADDRESS);
+        self.set_word(BusDiagnostic1::ADDRESS, (diagnostic & 0xFFFF_0000) | (diagnostic as u16).wrapping_add(1) as u32);
+    }
+
+    /// Count an attempt to send the frame at the tail of `fifo`, giving up once `TXAT` attempts are
+    /// used up
+    fn count_attempt(&mut self, fifo: u8) {
+        let limit = match self.fifo_control(fifo).txat() {
+            _ if !self.can_control().rtxat() => None,
+            RetransmissionAttempts::Disable => Some(1),
+            RetransmissionAttempts::Three => Some(4),
+            _ => None,
+        };
+        let state = &mut self.fifos[fifo as usize];
+        state.attempts = state.attempts.saturating_add(1);
+        if limit.is_some_and(|limit| state.attempts >= limit) {
+            state.attempts = 0;
+            self.can[fifo_status_address(fifo) as usize] |= STATUS_TXATIF;
+            self.can[fifo_control_address(fifo) as usize + 1] &= !CONTROL_TXREQ;
+        }
+    }
+
+    fn store_transmit_event(&mut self, header: [u8; 8]) {
+        let Some(layout) = self.tef_layout() else { return };
+        if self.tef.count == layout.size {
+            self.can[TransmitEventFIFOStatus::ADDRESS as usize] |= STATUS_TEFOVIF;
+            return;
+        }
+        let address = layout.object_address(self.tef.head) as usize;
+        self.ram[address..][..8].copy_from_slice(&header);
+        if layout.object_size > 8 {
+            let timestamp = self.word(TimeBaseCounter::ADDRESS);
+            self.ram[address + 8..][..4].copy_from_slice(&timestamp.to_le_bytes());
+        }
+        self.tef.head = (self.tef.head + 1) % layout.size;
+        self.tef.count += 1;
+    }
+
+    /// First enabled filter accepting `frame`, and the FIFO it points to
+    fn match_filter(&self, frame: &BusFrame) -> Option<(u8, u8)> {
+        let (sid, eid, extended) = frame.id_fields();
+        (0..32).find_map(|filter| {
+            let control = self.can[FilterControl::<0>::ADDRESS as usize + filter as usize];
+            if control & 0x80 == 0 {
+                return None;
+            }
+            let object = FilterObjectM::from_bytes(self.bytes(FilterObject::<0>::ADDRESS + 8 * filter as u16));
+            let mask = MaskM::from_bytes(self.bytes(Mask::<0>::ADDRESS + 8 * filter as u16));
+            let type_matches = !mask.mide() || object.exide() == extended;
+            let sid_matches = (sid ^ object.sid()) & mask.msid() == 0;
+            let eid_matches = !extended || (eid ^ object.eid()) & mask.meid() == 0;
+            (type_matches && sid_matches && eid_matches).then_some((filter, control & 0x1F))
+        })
+    }
+
+    fn receive(&mut self, frame: &BusFrame) {
+        if self.mode() == OperationMode::Classic && frame.fd {
+            self.set_bus_error(BDIAG1_NFORMERR);
+            return;
+        }
+        let Some((filter, fifo)) = self.match_filter(frame) else { return };
+        if fifo == 0 || self.is_transmit(fifo) {
+            return;
+        }
+        let layout = self.fifo_layout(fifo).unwrap();
+        let control = self.fifo_control(fifo);
+        if self.fifos[fifo as usize].count == layout.size {
+            self.can[fifo_status_address(fifo) as usize] |= STATUS_RXOVIF;
+            return;
+        }
+
+        let (sid, eid, extended) = frame.id_fields();
+        let header = ReceiveMessageObjectHeader::new()
+            .with_sid(sid)
+            .with_eid(eid)
+            .with_dlc(frame.dlc)
+            .with_ide(extended)
+            .with_rtr(frame.rtr)
+            .with_brs(frame.brs)
+            .with_fdf(frame.fd)
+            .with_esi(frame.esi)
+            .with_filthit(filter);
+        let mut address = layout.object_address(self.fifos[fifo as usize].head) as usize;
+        self.ram[address..][..8].copy_from_slice(&header.into_bytes());
+        address += 8;
+        if control.rxtsen() {
+            let timestamp = self.word(TimeBaseCounter::ADDRESS);
+            self.ram[address..][..4].copy_from_slice(&timestamp.to_le_bytes());
+            address += 4;
+        }
+        let payload_size = control.plsize().bytes();
+        let length = frame.data.len().min(payload_size);
+        self.ram[address..][..length].copy_from_slice(&frame.data[..length]);
+        if frame.data.len() > payload_size {
+            let diagnostic = self.word(BusDiagnostic1::ADDRESS);
+            self.set_word(BusDiagnostic1::ADDRESS, diagnostic | BDIAG1_DLCMM);
+        }
+
+        let state = &mut self.fifos[fifo as usize];
+        state.head = (state.head + 1) % layout.size;
+        state.count += 1;
+    }
+
+    /// Recompute the status registers from the FIFO states and flags
+    pub(super) fn refresh(&mut self) {
+        let mut receive = 0u32;
+        let mut transmit = 0u32;
+        let mut overflows = 0u32;
+        let mut attempts = 0u32;
+        let mut requests = 0u32;
+
+        for fifo in 0..32u8 {
+            let control = self.fifo_control(fifo);
+            let status = fifo_status_address(fifo) as usize;
+            let Some(layout) = self.fifo_layout(fifo) else {
+                self.can[status..][..12 - 4].fill(0);
+                continue;
+            };
+            let state = self.fifos[fifo as usize];
+            let transmitting = self.is_transmit(fifo);
+            let (not_full_not_empty, half, empty_full) = if transmitting {
+                (state.count < layout.size, fifo != 0 && state.count <= layout.size / 2, state.count == 0)
+            } else {
+                (state.count > 0, state.count > 0 && state.count >= layout.size.div_ceil(2), state.count == layout.size)
+            };
+            let flags = not_full_not_empty as u8 | (half as u8) << 1 | (empty_full as u8) << 2;
+            self.can[status] = (self.can[status] & 0xF8) | flags;
+            self.can[status + 1] = if transmitting { state.tail } else { state.head };
+            let user_object = if transmitting { state.head } else { state.tail };
+            self.set_word(fifo_user_address(fifo), layout.object_address(user_object) as u32);
+
+            let bit = 1 << fifo;
+            if flags & self.can[fifo_control_address(fifo) as usize] & 0x07 != 0 {
+                if transmitting { transmit |= bit } else { receive |= bit }
+            }
+            if self.can[status] & STATUS_RXOVIF != 0 && control.rxovie() {
+                overflows |= bit;
+            }
+            if self.can[status] & STATUS_TXATIF != 0 && control.txatie() {
+                attempts |= bit;
+            }
+            if transmitting && control.txreq() {
+                requests |= bit;
+            }
+        }
+        self.set_word(ReceiveInterruptStatus::ADDRESS, receive);
+        self.set_word(TransmitInterruptStatus::ADDRESS, transmit);
+        self.set_word(ReceiveOverflowInterruptStatus::ADDRESS, overflows);
+        self.set_word(TransmitAttemptInterruptStatus::ADDRESS, attempts);
+        self.set_word(TransmitRequest::ADDRESS, requests);
+
+        let tef_status = TransmitEventFIFOStatus::ADDRESS as usize;
+        let mut tef_pending = false;
+        match self.tef_layout() {
+            Some(layout) => {
+                let count = self.tef.count;
+                let flags = (count > 0) as u8
+                    | ((count > 0 && count >= layout.size.div_ceil(2)) as u8) << 1
+                    | ((count == layout.size) as u8) << 2;
+                self.can[tef_status] = (self.can[tef_status] & STATUS_TEFOVIF) | flags;
+                tef_pending = self.can[tef_status] & self.can[TransmitEventFIFOControl::ADDRESS as usize] & 0x0F != 0;
+                self.set_word(TransmitEventFIFOUserAddress::ADDRESS, layout.object_address(self.tef.tail) as u32);
+            },
+            None => {
+                self.can[tef_status] = 0;
+                self.set_word(TransmitEventFIFOUserAddress::ADDRESS, 0);
+            },
+        }
+
+        let crc = CRCStatus::from_bytes(self.bytes(CRCStatus::ADDRESS));
+        let spi_crc = (crc.crcerrif() && crc.crcerrie()) || (crc.ferrif() && crc.ferrie());
+
+        let mut interrupts = self.word(Interrupts::ADDRESS) & (0xFFFF_0000 | INT_TBCIF | INT_MODIF | INT_SERRIF | INT_CERRIF | INT_WAKIF | INT_IVMIF);
+        for (pending, flag) in [
+            (transmit != 0, INT_TXIF),
+            (receive != 0, INT_RXIF),
+            (tef_pending, INT_TEFIF),
+            (spi_crc, INT_SPICRCIF),
+            (attempts != 0, INT_TXATIF),
+            (overflows != 0, INT_RXOVIF),
+        ] {
+            if pending {
+                interrupts |= flag;
+            }
+        }
+        self.set_word(Interrupts::ADDRESS, interrupts);
+        self.refresh_interrupt_code(interrupts, receive, transmit);
+
+        let errors = TransmitReceiveErrorCount::ADDRESS as usize;
+        let (receive_errors, transmit_errors) = (self.can[errors], self.can[errors + 1]);
+        self.can[errors + 2] = (receive_errors > 95 || transmit_errors > 95) as u8
+            | ((receive_errors > 95) as u8) << 1
+            | ((transmit_errors > 95) as u8) << 2
+            | ((receive_errors > 127) as u8) << 3
+            | ((transmit_errors > 127) as u8) << 4
+            | (self.bus_off.is_some() as u8) << 5;
+
+        let oscillator = OscillatorControl::from_bytes(self.bytes(OscillatorControl::ADDRESS));
+        self.device[1] = oscillator.pllen() as u8 | (!oscillator.oscdis() as u8) << 2 | 1 << 4;
+    }
+
+    /// `CiVEC`, with the highest interrupt code of the enabled interrupts
+    fn refresh_interrupt_code(&mut self, interrupts: u32, receive: u32, transmit: u32) {
+        const NO_INTERRUPT: u8 = InterruptFlag::NoInterrupt as u8;
+        let enabled = interrupts & (interrupts >> 16);
+        let lowest = |fifos: u32| if fifos == 0 { NO_INTERRUPT } else { fifos.trailing_zeros() as u8 };
+        let rx_code = lowest(receive);
+        let tx_code = lowest(transmit);
+
+        let special = [
+            (INT_TXATIF, InterruptFlag::TransmitAttempt),
+            (INT_TEFIF, InterruptFlag::TransmitEventFIFO),
+            (INT_IVMIF, InterruptFlag::InvalidMessage),
+            (INT_MODIF, InterruptFlag::OperationModeChange),
+            (INT_TBCIF, InterruptFlag::TBCOverflow),
+            (INT_SERRIF, InterruptFlag::AddressError),
+            (INT_RXOVIF, InterruptFlag::ReceiveFIFOOverflow),
+            (INT_WAKIF, InterruptFlag::WakeUp),
+            (INT_CERRIF, InterruptFlag::Error),
+        ];
+        let fifos = if enabled & INT_RXIF != 0 { receive } else { 0 } | if enabled & INT_TXIF != 0 { transmit } else { 0 };
+        let icode = special.iter()
+            .find(|(flag, _)| enabled & flag != 0)
+            .map(|(_, code)| *code as u8)
+            .unwrap_or(if fifos == 0 { NO_INTERRUPT } else { 31 - fifos.leading_zeros() as u8 });
+
+        let filter_hit = if icode == rx_code { self.filter_hit(rx_code) } else { 0 };
+        let vector = icode as u32 | (filter_hit as u32) << 8 | (tx_code as u32) << 16 | (rx_code as u32) << 24;
+        self.set_word(InterruptCode::ADDRESS, vector);
+    }
+
+    /// Filter that matched the oldest message in RX FIFO `fifo`
+    fn filter_hit(&self, fifo: u8) -> u8 {
+        let Some(layout) = self.fifo_layout(fifo) else { return 0 };
+        let address = layout.object_address(self.fifos[fifo as usize].tail) as usize;
+        let header: [u8; 8] = self.ram[address..][..8].try_into().unwrap();
+        ReceiveMessageObjectHeader::from_bytes(header).filthit()
+    }
+}
+
+/// The simulated controller, standing in for its SPI device
+///
+/// Clones share the same controller, so a test can keep one to look at the bus while the driver
+/// owns another.
+#[derive(Clone)]
+pub struct Simulator {
+    controller: Rc<RefCell<Controller>>,
+    bus: Option<VirtualBus>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self { controller: Rc::new(RefCell::new(Controller::new())), bus: None }
+    }
+
+    /// A controller that is a node on `bus`, which decides when its frames are sent
+    pub(super) fn on_bus(bus: VirtualBus) -> (Self, Rc<RefCell<Controller>>) {
+        let mut controller = Controller::new();
+        controller.on_bus = true;
+        let controller = Rc::new(RefCell::new(controller));
+        (Self { controller: controller.clone(), bus: Some(bus) }, controller)
+    }
+
+    fn transaction(&self, operations: &mut [Operation<'_, u8>]) {
+        self.controller.borrow_mut().transaction(operations);
+        if let Some(bus) = &self.bus {
+            bus.advance();
+        }
+    }
+
+    /// Frames the controller put on the bus since the last call
+    pub fn take_transmitted(&self) -> Vec<BusFrame> {
+        self.controller.borrow_mut().transmitted.drain(..).collect()
+    }
+
+    /// Put a frame from another node on the bus, which the controller receives straight away
+    ///
+    /// The frame is missed if the controller isn't in a mode that receives from the bus. In Sleep
+    /// mode it wakes the controller up if `WAKIE` is set.
+    pub fn send(&self, frame: BusFrame) {
+        self.controller.borrow_mut().bus_frame(&frame);
+    }
+
+    /// Whether another node acknowledges the frames the controller sends, true by default. Nodes
+    /// on a virtual bus are acknowledged by the other nodes instead.
+    pub fn set_acknowledge(&self, acknowledge: bool) {
+        self.controller.borrow_mut().acknowledge = acknowledge;
+    }
+
+    pub fn mode(&self) -> OperationMode {
+        self.controller.borrow().mode()
+    }
+
+    /// Flip a data bit in each of the next `transactions` `ReadCRC`, `WriteCRC` and `WriteSafe`
+    /// instructions, as noise on the SPI lines would
+    pub fn corrupt(&self, transactions: usize) {
+        self.controller.borrow_mut().corruptions = transactions;
+    }
+
+    /// Number of SPI transactions so far
+    pub fn transactions(&self) -> usize {
+        self.controller.borrow().transactions
+    }
+
+    /// Current value of the SFR at `address`
+    pub fn register(&self, address: u16) -> u32 {
+        self.controller.borrow().word(address)
+    }
+
+    /// Whether the INT pin is asserted, i.e. an enabled interrupt is pending
+    pub fn interrupt_asserted(&self) -> bool {
+        self.line_asserted(InterruptLine::Int)
+    }
+
+    /// One of the interrupt outputs, to wait on
+    pub fn interrupt_pin(&self, line: InterruptLine) -> InterruptPin {
+        InterruptPin { simulator: self.clone(), line, wakeups: Rc::new(Cell::new(0)) }
+    }
+
+    fn line_asserted(&self, line: InterruptLine) -> bool {
+        let interrupts = self.register(Interrupts::ADDRESS);
+        let mask = match line {
+            InterruptLine::Int => 0xFFFF,
+            InterruptLine::Int0 => INT_TXIF,
+            InterruptLine::Int1 => INT_RXIF,
+        };
+        interrupts & (interrupts >> 16) & mask != 0
+    }
+}
+
+/// Interrupt outputs of the controller
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InterruptLine {
+    /// Asserted while any enabled interrupt is pending
+    Int,
+    /// Asserted while the transmit interrupt is pending
+    Int0,
+    /// Asserted while the receive interrupt is pending
+    Int1,
+}
+
+/// An interrupt output of a [`Simulator`]
+///
+/// Waiting stays pending until the line asserts, so another future has to make that happen.
+#[derive(Clone)]
+pub struct InterruptPin {
+    simulator: Simulator,
+    line: InterruptLine,
+    wakeups: Rc<Cell<usize>>,
+}
+
+impl InterruptPin {
+    /// Number of waits that completed so far, shared between clones
+    pub fn wakeups(&self) -> usize {
+        self.wakeups.get()
+    }
+}
+
+impl embedded_hal::digital::ErrorType for InterruptPin {
+    type Error = Infallible;
+}
+
+impl InterruptPin {
+    /// Wait until `done` holds for the previous and the current level of the line, `true` while
+    /// asserted, i.e. low
+    async fn wait_until(&mut self, done: impl Fn(bool, bool) -> bool) {
+        let mut previous = self.simulator.line_asserted(self.line);
+        poll_fn(|context| {
+            let asserted = self.simulator.line_asserted(self.line);
+            if done(previous, asserted) {
+                Poll::Ready(())
+            } else {
+                previous = asserted;
+                context.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }).await;
+        self.wakeups.set(self.wakeups.get() + 1);
+    }
+}
+
+impl Wait for InterruptPin {
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_until(|_, asserted| asserted).await;
+        Ok(())
+    }
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_until(|_, asserted| !asserted).await;
+        Ok(())
+    }
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_until(|previous, asserted| previous && !asserted).await;
+        Ok(())
+    }
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_until(|previous, asserted| !previous && asserted).await;
+        Ok(())
+    }
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_until(|previous, asserted| previous != asserted).await;
+        Ok(())
+    }
+}
+
+impl ErrorType for Simulator {
+    type Error = Infallible;
+}
+
+impl embedded_hal::spi::SpiDevice for Simulator {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        Simulator::transaction(self, operations);
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::spi::SpiDevice for Simulator {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        Simulator::transaction(self, operations);
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{block_on, NodeSetup};
+use common::simulator::Simulator;
+use embedded_can::StandardId;
+use mcp25xxfd::config::{FIFOConfig, FilterConfig, MaskConfig};
+use mcp25xxfd::registers::*;
+use mcp25xxfd::MCP25xxFD;
+
+/// A driver left in Configuration mode, so that FIFOs can still be added
+fn configured() -> (MCP25xxFD<Simulator>, Simulator) {
+    let simulator = Simulator::new();
+    let driver = NodeSetup { mode: OperationMode::Configuration, ..Default::default() }.driver(&simulator);
+    (driver, simulator)
+}
+
+#[test]
+fn test_register_names() {
+    let (mut driver, _) = configured();
+    let dump = block_on(driver.dump_registers()).unwrap();
+    let name = |address| {
+        let (name, _, _) = dump.registers().find(|(_, at, _)| *at == address).unwrap();
+        format!("{name:?}")
+    };
+
+    assert_eq!(name(0x050), "CiTXQCON");
+    assert_eq!(name(0x05C), "CiFIFOCON1");
+    assert_eq!(name(0x060), "CiFIFOSTA1");
+    assert_eq!(name(0x064), "CiFIFOUA1");
+    assert_eq!(name(0x074), "CiFIFOCON3");
+    assert_eq!(name(0x1C4), "CiFIFOCON31");
+    assert_eq!(name(0x1CC), "CiFIFOUA31");
+    assert_eq!(name(0x1D0), "CiFLTCON0");
+    assert_eq!(name(0x1EC), "CiFLTCON7");
+    assert_eq!(name(0x1F0), "CiFLTOBJ0");
+    assert_eq!(name(0x21C), "CiMASK5");
+    assert_eq!(name(0x2EC), "CiMASK31");
+    assert_eq!(name(0xE04), "IOCON");
+    // Reserved
+    assert!(dump.registers().all(|(_, address, _)| address != 0x2F0));
+}
+
+#[test]
+fn test_diff() {
+    let (mut driver, _) = configured();
+    let before = block_on(driver.dump_registers()).unwrap();
+    block_on(async {
+        driver.configure_fifo(FIFOConfig::<3>::rx_with_size(6, PayloadSize::Bytes16)).await.unwrap();
+        driver.configure_filter(
+            FilterConfig::<5, 3>::from_id(StandardId::new(0x345).unwrap()),
+            MaskConfig::<5>::match_exact(),
+        ).await.unwrap();
+    });
+    let after = block_on(driver.dump_registers()).unwrap();
+    let diff = before.diff(&after);
+
+    // FIFO 3 takes RAM away from the FIFOs after it, moving their next message object
+    let mut expected = vec!["CiFIFOCON3".to_string()];
+    expected.extend((4..32).map(|fifo| format!("CiFIFOUA{fifo}")));
+    expected.extend(["CiFLTCON1", "CiFLTOBJ5", "CiMASK5"].map(String::from));
+    let changed: Vec<_> = diff.iter().map(|change| format!("{:?}", change.name)).collect();
+    assert_eq!(changed, expected);
+    let fifo = diff.iter().next().unwrap();
+    assert_eq!(fifo.address, 0x074);
+    assert_eq!(fifo.changed_bits(), fifo.before ^ fifo.after);
+
+    assert_eq!(after.fifo_control(3).unwrap().fsize(), 5);
+    assert_eq!(after.fifo_control(3).unwrap().plsize(), PayloadSize::Bytes16);
+    assert!(after.filter_control(1).unwrap().flten1());
+    assert_eq!(after.filter_control(1).unwrap().f1bp(), 3);
+    assert_eq!(after.filter_object(5).unwrap().sid(), 0x345);
+    assert_eq!(after.mask(5).unwrap().msid(), 0x7FF);
+    assert!(before.diff(&before).is_empty());
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::block_on;
+use common::simulator::{BusFrame, Simulator};
+use embedded_can::StandardId;
+use mcp25xxfd::config::{Config, FIFOConfig, FilterConfig, MaskConfig};
+use mcp25xxfd::ecc::{ECCErrorKind, ECCEvent, RAMLocation};
+use mcp25xxfd::frame::Frame;
+use mcp25xxfd::registers::*;
+use mcp25xxfd::MCP25xxFD;
+
+const RAM_START: u16 = 0x400;
+
+/// Message RAM laid out as
+///
+/// | Owner | Objects | Object size | Offsets  |
+/// |-------|---------|-------------|----------|
+/// | TEF   | 3       | 8 + 4       | 0..36    |
+/// | TXQ   | 2       | 8 + 16      | 36..84   |
+/// | FIFO1 | 3       | 8 + 4 + 8   | 84..144  |
+/// | FIFO2 | 1       | 8 + 64      | 144..216 |
+///
+/// followed by FIFOs 3 to 31 at their reset size of one 8 byte object, up to 680.
+fn configured(simulator: &Simulator) -> MCP25xxFD<Simulator> {
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    let config = Config { txq_enabled: true, tx_event_fifo_enabled: true, ..Config::default() };
+    block_on(async {
+        driver.reset_and_apply_config(&config).await.unwrap();
+        let tef = TransmitEventFIFOControl::new().with_fsize(2).with_teftsen(true);
+        driver.write_register(tef).await.unwrap();
+        driver.configure_fifo(FIFOConfig::<0>::tx_with_size(2, PayloadSize::Bytes16)).await.unwrap();
+        driver.configure_fifo(FIFOConfig::<1>::rx_with_size(3, PayloadSize::Bytes8)).await.unwrap();
+        let mut fifo: FIFOControl<1> = driver.read_register().await.unwrap();
+        fifo.contents.set_rxtsen(true);
+        driver.write_register(fifo).await.unwrap();
+        driver.configure_fifo(FIFOConfig::<2>::tx_with_size(1, PayloadSize::Bytes64)).await.unwrap();
+    });
+    driver
+}
+
+#[test]
+fn test_ram_location() {
+    let simulator = Simulator::new();
+    let mut driver = configured(&simulator);
+    let location = |driver: &mut MCP25xxFD<Simulator>, offset: u16| {
+        block_on(driver.ram_location(RAM_START + offset)).unwrap()
+    };
+
+    assert_eq!(location(&mut driver, 0), RAMLocation::TransmitEventFIFO { object: 0 });
+    assert_eq!(location(&mut driver, 12), RAMLocation::TransmitEventFIFO { object: 1 });
+    assert_eq!(location(&mut driver, 35), RAMLocation::TransmitEventFIFO { object: 2 });
+    assert_eq!(location(&mut driver, 36), RAMLocation::TransmitQueue { object: 0 });
+    assert_eq!(location(&mut driver, 59), RAMLocation::TransmitQueue { object: 0 });
+    assert_eq!(location(&mut driver, 60), RAMLocation::TransmitQueue { object: 1 });
+    assert_eq!(location(&mut driver, 84), RAMLocation::FIFO { fifo: 1, object: 0 });
+    assert_eq!(location(&mut driver, 124), RAMLocation::FIFO { fifo: 1, object: 2 });
+    assert_eq!(location(&mut driver, 143), RAMLocation::FIFO { fifo: 1, object: 2 });
+    assert_eq!(location(&mut driver, 144), RAMLocation::FIFO { fifo: 2, object: 0 });
+    assert_eq!(location(&mut driver, 215), RAMLocation::FIFO { fifo: 2, object: 0 });
+    assert_eq!(location(&mut driver, 216), RAMLocation::FIFO { fifo: 3, object: 0 });
+    assert_eq!(location(&mut driver, 679), RAMLocation::FIFO { fifo: 31, object: 0 });
+    assert_eq!(location(&mut driver, 680), RAMLocation::Unallocated);
+    assert_eq!(location(&mut driver, 2047), RAMLocation::Unallocated);
+    // Outside message RAM
+    assert_eq!(block_on(driver.ram_location(RAM_START - 4)).unwrap(), RAMLocation::Unallocated);
+    assert_eq!(block_on(driver.ram_location(RAM_START + 2048)).unwrap(), RAMLocation::Unallocated);
+
+    // Without the TEF and TXQ, FIFO 1 starts at the beginning of RAM
+    block_on(driver.reset_and_apply_config(&Config::default())).unwrap();
+    assert_eq!(location(&mut driver, 0), RAMLocation::FIFO { fifo: 1, object: 0 });
+    assert_eq!(location(&mut driver, 16), RAMLocation::FIFO { fifo: 2, object: 0 });
+}
+
+#[test]
+fn test_ram_layout_cached() {
+    let simulator = Simulator::new();
+    let mut driver = configured(&simulator);
+    block_on(driver.set_mode(OperationMode::Normal)).unwrap();
+    block_on(driver.ram_location(RAM_START)).unwrap();
+
+    // Read back once outside of Configuration mode
+    let transactions = simulator.transactions();
+    assert_eq!(block_on(driver.ram_location(RAM_START + 144)).unwrap(), RAMLocation::FIFO { fifo: 2, object: 0 });
+    assert_eq!(simulator.transactions(), transactions);
+
+    // and again after the mode changed, in case FIFOs were resized
+    block_on(async {
+        driver.set_mode(OperationMode::Configuration).await.unwrap();
+        driver.configure_fifo(FIFOConfig::<2>::tx_with_size(2, PayloadSize::Bytes64)).await.unwrap();
+        driver.set_mode(OperationMode::Normal).await.unwrap();
+    });
+    assert_eq!(block_on(driver.ram_location(RAM_START + 216)).unwrap(), RAMLocation::FIFO { fifo: 2, object: 1 });
+    assert_eq!(block_on(driver.ram_location(RAM_START + 288)).unwrap(), RAMLocation::FIFO { fifo: 3, object: 0 });
+}
+
+fn event(location: RAMLocation) -> ECCEvent {
+    ECCEvent { kind: ECCErrorKind::DoubleBit, address: RAM_START, location }
+}
+
+#[test]
+fn test_recover_ecc_error() {
+    let simulator = Simulator::new();
+    let mut driver = configured(&simulator);
+    block_on(async {
+        driver.configure_filter(
+            FilterConfig::<0, 1>::from_id(StandardId::ZERO),
+            MaskConfig::<0>::match_anything(),
+        ).await.unwrap();
+        driver.set_mode(OperationMode::Normal).await.unwrap();
+    });
+    simulator.set_acknowledge(false);
+
+    // Frames pending in the RX FIFO, the TXQ and the TX FIFO are dropped by the reset
+    for id in 0..2 {
+        simulator.send(BusFrame::new(StandardId::new(id).unwrap(), &[0; 8]));
+    }
+    let frame = Frame::new(StandardId::new(0x123).unwrap(), &[0; 8]).unwrap();
+    block_on(async {
+        driver.transmit::<0>(&frame).await.unwrap();
+        driver.transmit::<2>(&frame).await.unwrap();
+    });
+    // TFNRFNIF of the RX FIFO
+    let rx_not_empty = || simulator.register(FIFOStatus::<1>::ADDRESS) & 1 != 0;
+    assert!(rx_not_empty());
+    assert_ne!(simulator.register(TransmitRequest::ADDRESS), 0);
+    block_on(async {
+        driver.recover_ecc_error(&event(RAMLocation::FIFO { fifo: 1, object: 1 })).await.unwrap();
+        driver.recover_ecc_error(&event(RAMLocation::TransmitQueue { object: 0 })).await.unwrap();
+        driver.recover_ecc_error(&event(RAMLocation::FIFO { fifo: 2, object: 0 })).await.unwrap();
+    });
+    assert!(!rx_not_empty());
+    assert_eq!(simulator.register(TransmitRequest::ADDRESS), 0);
+    let txq = TransmitQueueStatus::parse(&simulator.register(TransmitQueueStatus::ADDRESS).to_le_bytes());
+    assert!(txq.txqeif());
+
+    // The TEF holds the event of the frame that got through
+    simulator.set_acknowledge(true);
+    block_on(driver.transmit::<2>(&frame)).unwrap();
+    assert_eq!(simulator.take_transmitted().len(), 1);
+    let tef_status = || TransmitEventFIFOStatus::parse(&simulator.register(TransmitEventFIFOStatus::ADDRESS).to_le_bytes());
+    assert!(tef_status().tefneif());
+    block_on(driver.recover_ecc_error(&event(RAMLocation::TransmitEventFIFO { object: 0 }))).unwrap();
+    assert!(!tef_status().tefneif());
+
+    assert!(block_on(driver.recover_ecc_error(&event(RAMLocation::Unallocated))).is_err());
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use common::block_on;
+use embedded_can::ExtendedId;
+use embedded_hal_async::spi::{ErrorType, Operation, SpiDevice};
+use mcp25xxfd::config::{FilterConfig, MaskConfig};
+use mcp25xxfd::frame::Frame;
+use mcp25xxfd::MCP25xxFD;
+
+/// Registers and message RAM as plain memory, recording what the driver writes
+#[derive(Clone)]
+struct Memory(Rc<RefCell<Vec<u8>>>);
+
+impl ErrorType for Memory {
+    type Error = core::convert::Infallible;
+}
+
+impl SpiDevice for Memory {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let mut memory = self.0.borrow_mut();
+        let mut write = false;
+        let mut address = None;
+        for operation in operations {
+            let (mut read, mut written): (&mut [u8], &[u8]) = match operation {
+                Operation::Write(data) => (&mut [], data),
+                Operation::Read(data) => (data, &[]),
+                Operation::Transfer(read, written) => (read, written),
+                _ => continue,
+            };
+            if address.is_none() {
+                write = written[0] >> 4 == 0b0010;
+                address = Some((((written[0] & 0xF) as usize) << 8) | written[1] as usize);
+                written = &written[2..];
+                if !read.is_empty() {
+                    read = &mut read[2..];
+                }
+            }
+            let at = address.unwrap();
+            let length = read.len().max(written.len());
+            if write {
+                memory[at..at + length].copy_from_slice(written);
+            } else {
+                read.copy_from_slice(&memory[at..at + length]);
+            }
+            address = Some(at + length);
+        }
+        Ok(())
+    }
+}
+
+/// ID[28:18] go in SID and ID[17:0] in EID, which follows SID from bit 11 of the first word
+const ID: u32 = 0x1234_5678;
+const ID_WORD: [u8; 4] = [0x8D, 0xC4, 0xB3, 0x02];
+
+fn driver() -> (MCP25xxFD<Memory>, Memory) {
+    let memory = Memory(Rc::new(RefCell::new(vec![0; 0x1000])));
+    (MCP25xxFD::new(memory.clone()), memory)
+}
+
+#[test]
+fn test_transmit_header() {
+    let (mut driver, memory) = driver();
+    // FIFO 1 not full, its next message object at the start of RAM
+    memory.0.borrow_mut()[0x060] = 0x01;
+    block_on(driver.transmit::<1>(&Frame::new(ExtendedId::new(ID).unwrap(), &[0; 4]).unwrap())).unwrap();
+
+    let ram = &memory.0.borrow()[0x400..0x408];
+    assert_eq!(ram[..4], ID_WORD);
+    // DLC 4 and IDE
+    assert_eq!(ram[4..], [0x14, 0, 0, 0]);
+}
+
+#[test]
+fn test_receive_header() {
+    let (mut driver, memory) = driver();
+    let mut registers = memory.0.borrow_mut();
+    // RXIF, FIFO 1 not empty with its next message object at RAM offset 0x100
+    registers[0x01C] = 0x02;
+    registers[0x020] = 0x02;
+    registers[0x065] = 0x01;
+    registers[0x500..0x504].copy_from_slice(&ID_WORD);
+    registers[0x504] = 0x14;
+    drop(registers);
+
+    let (_, frame) = block_on(driver.receive(None)).unwrap().unwrap();
+    assert_eq!(frame.id(), ExtendedId::new(ID).unwrap().into());
+}
+
+#[test]
+fn test_filter_and_mask() {
+    let (mut driver, memory) = driver();
+    block_on(driver.configure_filter(
+        FilterConfig::<0, 1>::from_id(ExtendedId::new(ID).unwrap()),
+        MaskConfig::<0>::from_id(ExtendedId::MAX),
+    )).unwrap();
+
+    let registers = memory.0.borrow();
+    // EXIDE in bit 30 of the filter
+    assert_eq!(registers[0x1F0..0x1F4], [0x8D, 0xC4, 0xB3, 0x42]);
+    assert_eq!(registers[0x1F4..0x1F8], [0xFF, 0xFF, 0xFF, 0x1F]);
+}
@@ -0,0 +1,868 @@
+mod common;
+
+use std::convert::Infallible;
+use std::ops::Range;
+use common::{block_on, NodeSetup, RX_FIFO, TX_FIFO};
+use common::simulator::{BusFrame, InterruptLine, Simulator};
+use embedded_can::{ExtendedId, Id, StandardId};
+use mcp25xxfd::config::{BandwidthSharing, ClockOutput, Config, FIFOConfig, FIFOInterrupts, FilterConfig, InterruptConfig, MaskConfig, PinConfig, RetransmissionPolicy, SPICRCConfig, TransceiverStandby};
+use embedded_hal::digital::{OutputPin, PinState};
+use embedded_hal::spi::ErrorKind;
+use embedded_hal_async::spi::{ErrorType, Operation, SpiDevice};
+use embedded_hal_async::digital::Wait;
+use embassy_futures::join::join;
+use embassy_futures::yield_now;
+use mcp25xxfd::crc::{crc16, SPICRCCounters};
+use mcp25xxfd::frame::Frame;
+use mcp25xxfd::interrupt::{InterruptEvent, InterruptPin};
+use mcp25xxfd::monitor::{MonitorConfig, MonitorEvent};
+use mcp25xxfd::power::{PendingTransmissions, SleepConfig, WakeUpSource};
+use mcp25xxfd::gpio::PinMode;
+use mcp25xxfd::registers::*;
+use mcp25xxfd::self_test::LoopbackMode;
+use mcp25xxfd::transmit::{BusError, TransmitOutcome};
+use mcp25xxfd::MCP25xxFD;
+
+const OUTCOME_POLLS: usize = 10_000;
+
+#[test]
+fn test_config_reaches_normal_mode() {
+    let simulator = Simulator::new();
+    NodeSetup::default().driver(&simulator);
+    assert_eq!(simulator.mode(), OperationMode::Normal);
+}
+
+#[test]
+fn test_transmit() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let standard = StandardId::new(0x123).unwrap();
+    let extended = ExtendedId::new(0x1234_5678).unwrap();
+    block_on(async {
+        driver.transmit::<TX_FIFO>(&Frame::new(standard, &[1, 2, 3, 4]).unwrap()).await.unwrap();
+        driver.transmit::<TX_FIFO>(&Frame::new(extended, &[0xAA; 16]).unwrap()).await.unwrap();
+    });
+    assert_eq!(simulator.take_transmitted(), [
+        BusFrame::new(standard, &[1, 2, 3, 4]),
+        BusFrame::new(extended, &[0xAA; 16]),
+    ]);
+}
+
+#[test]
+fn test_filtered_receive() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let accepted = ExtendedId::new(0x0ABC_DEF0).unwrap();
+    block_on(driver.configure_filter(
+        FilterConfig::<0, RX_FIFO>::from_id(accepted),
+        MaskConfig::<0> { match_id_type: true, id: ExtendedId::MAX.into() },
+    )).unwrap();
+
+    simulator.send(BusFrame::new(ExtendedId::new(0x0ABC_DEF1).unwrap(), &[1; 4]));
+    // Same SID as the accepted identifier, only rejected because the mask includes the type
+    simulator.send(BusFrame::new(StandardId::new(0x2AF).unwrap(), &[2; 4]));
+    simulator.send(BusFrame::new(accepted, &[3; 12]));
+    let (fifo, frame) = block_on(driver.receive(None)).unwrap().unwrap();
+    assert_eq!(fifo, RX_FIFO);
+    assert_eq!(frame.id(), Id::Extended(accepted));
+    assert_eq!(frame.data(), [3; 12]);
+    assert!(block_on(driver.receive(None)).unwrap().is_none());
+}
+
+#[test]
+fn test_receive_overflow() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    for i in 0..6 {
+        simulator.send(BusFrame::new(StandardId::new(i).unwrap(), &[i as u8; 8]));
+    }
+    block_on(async {
+        for i in 0..4 {
+            let (_, frame) = driver.receive(None).await.unwrap().unwrap();
+            assert_eq!(frame.raw_id(), i);
+        }
+        assert!(driver.receive(None).await.unwrap().is_none());
+    });
+    assert_eq!(driver.receive_overflows().count(RX_FIFO), 1);
+}
+
+#[test]
+fn test_receive_drains_half_full_fifo() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let mut rx_fifo = FIFOConfig::<RX_FIFO>::rx_with_size(4, PayloadSize::Bytes64);
+    rx_fifo.interrupts = FIFOInterrupts { half: true, ..Default::default() };
+    block_on(async {
+        driver.set_mode(OperationMode::Configuration).await.unwrap();
+        driver.configure_fifo(rx_fifo).await.unwrap();
+        driver.set_mode(OperationMode::Normal).await.unwrap();
+    });
+
+    simulator.send(BusFrame::new(StandardId::new(0).unwrap(), &[0; 8]));
+    assert!(block_on(driver.receive(None)).unwrap().is_none());
+    for i in 1..3 {
+        simulator.send(BusFrame::new(StandardId::new(i).unwrap(), &[i as u8; 8]));
+    }
+    // Once half full, the FIFO is read until empty even though the flag clears after the first
+    block_on(async {
+        for i in 0..3 {
+            let (_, frame) = driver.receive(None).await.unwrap().unwrap();
+            assert_eq!(frame.raw_id(), i);
+        }
+        assert!(driver.receive(None).await.unwrap().is_none());
+    });
+}
+
+#[test]
+fn test_receive_waits_on_shared_pin() {
+    let simulator = Simulator::new();
+    let pin = simulator.interrupt_pin(InterruptLine::Int);
+    let mut driver = MCP25xxFD::with_interrupt_pins(simulator.clone(), InterruptPin(pin.clone()));
+    block_on(async {
+        driver.reset_and_apply_config(&Config::default()).await.unwrap();
+        driver.configure_fifo(FIFOConfig::<RX_FIFO>::rx_with_size(4, PayloadSize::Bytes8)).await.unwrap();
+        driver.configure_fifo(FIFOConfig::<3>::rx_with_size(4, PayloadSize::Bytes8)).await.unwrap();
+        driver.configure_filter(FilterConfig::<0, RX_FIFO>::from_id(StandardId::new(0x100).unwrap()), MaskConfig::<0>::match_exact()).await.unwrap();
+        driver.configure_filter(FilterConfig::<1, 3>::from_id(StandardId::ZERO), MaskConfig::<1>::match_anything()).await.unwrap();
+        driver.set_mode(OperationMode::Normal).await.unwrap();
+    });
+    let enables = simulator.register(Interrupts::ADDRESS) >> 16;
+    assert!(!simulator.interrupt_asserted());
+
+    let sender = async {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+        simulator.send(BusFrame::new(StandardId::new(0x100).unwrap(), &[1; 4]));
+    };
+    let (received, ()) = block_on(join(driver.receive(Some(RX_FIFO)), sender));
+    assert_eq!(received.unwrap().unwrap().1.raw_id(), 0x100);
+    assert_eq!(pin.wakeups(), 1);
+
+    // A frame in the FIFO excluded by the restriction asserts the pin as well, which ends the
+    // wait straight away without a frame
+    simulator.send(BusFrame::new(StandardId::new(0x200).unwrap(), &[2; 4]));
+    assert!(block_on(driver.receive(Some(RX_FIFO))).unwrap().is_none());
+    assert_eq!(pin.wakeups(), 2);
+    let (fifo, _) = block_on(driver.receive(None)).unwrap().unwrap();
+    assert_eq!(fifo, 3);
+    // The enables are never touched while waiting
+    assert_eq!(simulator.register(Interrupts::ADDRESS) >> 16, enables);
+}
+
+#[test]
+fn test_interrupt_pin_edges() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let mut pin = simulator.interrupt_pin(InterruptLine::Int);
+    assert!(!simulator.interrupt_asserted());
+    block_on(pin.wait_for_high()).unwrap();
+
+    // Asserting the active low pin is a falling edge
+    let sender = async {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+        simulator.send(BusFrame::new(StandardId::new(0x100).unwrap(), &[1; 4]));
+    };
+    let (waited, ()) = block_on(join(pin.wait_for_falling_edge(), sender));
+    waited.unwrap();
+    block_on(pin.wait_for_low()).unwrap();
+
+    // and reading the frame releases it again
+    let receiver = async {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+        driver.receive(None).await.unwrap().unwrap();
+    };
+    let (waited, ()) = block_on(join(pin.wait_for_rising_edge(), receiver));
+    waited.unwrap();
+
+    let sender = async {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+        simulator.send(BusFrame::new(StandardId::new(0x100).unwrap(), &[1; 4]));
+    };
+    let (waited, ()) = block_on(join(pin.wait_for_any_edge(), sender));
+    waited.unwrap();
+    assert!(simulator.interrupt_asserted());
+    assert_eq!(pin.wakeups(), 5);
+}
+
+#[test]
+fn test_transmit_waits_for_room() {
+    let simulator = Simulator::new();
+    let pin = simulator.interrupt_pin(InterruptLine::Int);
+    let mut driver = MCP25xxFD::with_interrupt_pins(simulator.clone(), InterruptPin(pin.clone()));
+    // The missing acknowledge below would raise CERRIF
+    let mut config = Config::default();
+    config.interrupts.transmit = true;
+    config.interrupts.bus_error = false;
+    let mut tx_fifo = FIFOConfig::<TX_FIFO>::tx_with_size(1, PayloadSize::Bytes8);
+    tx_fifo.interrupts.not_full_not_empty = true;
+    NodeSetup { config, tx_fifo, ..Default::default() }.apply(&mut driver);
+    let enables = simulator.register(Interrupts::ADDRESS) >> 16;
+    let frame = Frame::new(StandardId::new(0x123).unwrap(), &[0; 4]).unwrap();
+
+    // Unacknowledged, the frame stays in the FIFO until aborted
+    simulator.set_acknowledge(false);
+    block_on(driver.transmit::<TX_FIFO>(&frame)).unwrap();
+    assert!(!simulator.interrupt_asserted());
+    let aborting = async {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+        let mut controller = MCP25xxFD::new(simulator.clone());
+        controller.abort_fifo(TX_FIFO).await.unwrap();
+        simulator.set_acknowledge(true);
+    };
+    let (sent, ()) = block_on(join(driver.transmit::<TX_FIFO>(&frame), aborting));
+    sent.unwrap();
+    assert_eq!(pin.wakeups(), 1);
+    assert_eq!(simulator.register(Interrupts::ADDRESS) >> 16, enables);
+    assert_eq!(simulator.take_transmitted().len(), 1);
+}
+
+#[test]
+fn test_outcome_waits_on_pin() {
+    let simulator = Simulator::new();
+    let pin = simulator.interrupt_pin(InterruptLine::Int);
+    let mut driver = MCP25xxFD::with_interrupt_pins(simulator.clone(), InterruptPin(pin.clone()));
+    let mut config = Config::default();
+    config.interrupts.transmit = true;
+    config.interrupts.bus_error = false;
+    let mut tx_fifo = FIFOConfig::<TX_FIFO>::tx_with_size(1, PayloadSize::Bytes8);
+    tx_fifo.interrupts.empty_full = true;
+    NodeSetup { config, tx_fifo, ..Default::default() }.apply(&mut driver);
+    let frame = Frame::new(StandardId::new(0x123).unwrap(), &[0; 4]).unwrap();
+
+    simulator.set_acknowledge(false);
+    let ticket = block_on(driver.transmit_tracked::<TX_FIFO>(&frame)).unwrap();
+    let acknowledging = async {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+        simulator.set_acknowledge(true);
+        // Let the controller run
+        let mut controller = MCP25xxFD::new(simulator.clone());
+        controller.read_register::<CANControl>().await.unwrap();
+    };
+    let (outcome, ()) = block_on(join(driver.wait_for_outcome(&ticket, 2), acknowledging));
+    assert!(matches!(outcome.unwrap(), TransmitOutcome::Sent { .. }));
+    assert_eq!(pin.wakeups(), 1);
+}
+
+#[test]
+fn test_outcome_gives_up() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    simulator.set_acknowledge(false);
+    let frame = Frame::new(StandardId::new(0x123).unwrap(), &[0; 4]).unwrap();
+    block_on(async {
+        let ticket = driver.transmit_tracked::<TX_FIFO>(&frame).await.unwrap();
+        assert!(driver.wait_for_outcome(&ticket, 5).await.is_err());
+        assert_eq!(driver.transmit_outcome(&ticket).await.unwrap(), None);
+    });
+}
+
+#[test]
+fn test_bandwidth_sharing() {
+    let simulator = Simulator::new();
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    let config = Config { bandwidth_sharing: BandwidthSharing::Delay64, ..Config::default() };
+    let txbws = || CANControl::parse(&simulator.register(CANControl::ADDRESS).to_le_bytes()).txbws();
+    block_on(async {
+        driver.reset_and_apply_config(&config).await.unwrap();
+        assert_eq!(txbws(), 6);
+        assert_eq!(driver.bandwidth_sharing().await.unwrap(), BandwidthSharing::Delay64);
+
+        driver.set_mode(OperationMode::Normal).await.unwrap();
+        driver.set_bandwidth_sharing(BandwidthSharing::Delay4096).await.unwrap();
+        assert_eq!(simulator.mode(), OperationMode::Normal);
+        assert_eq!(txbws(), 12);
+        assert_eq!(driver.bandwidth_sharing().await.unwrap(), BandwidthSharing::Delay4096);
+
+        driver.set_mode(OperationMode::ListenOnly).await.unwrap();
+        driver.set_bandwidth_sharing(BandwidthSharing::NoDelay).await.unwrap();
+        assert_eq!(simulator.mode(), OperationMode::ListenOnly);
+        assert_eq!(driver.bandwidth_sharing().await.unwrap(), BandwidthSharing::NoDelay);
+    });
+}
+
+#[test]
+fn test_bandwidth_sharing_bits() {
+    for bits in 0..12 {
+        let delay = BandwidthSharing::from_bits(bits);
+        assert_eq!(delay.bits(), bits);
+        assert_eq!(delay.bit_times(), if bits == 0 { 0 } else { 1 << bits });
+    }
+    // 11xx all select the longest delay
+    for bits in 12..16 {
+        assert_eq!(BandwidthSharing::from_bits(bits), BandwidthSharing::Delay4096);
+        assert_eq!(BandwidthSharing::from_bits(bits).bit_times(), 4096);
+    }
+}
+
+/// A driver in Normal mode with the receive, transmit, bus error and mode change interrupts
+/// enabled, TX FIFO `M` raising its interrupt while not full, and RX FIFO 2 taking frames 0x100 to
+/// 0x1FF through filter 1
+fn with_events<const M: u8>(simulator: &Simulator) -> MCP25xxFD<Simulator> {
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    let config = Config {
+        interrupts: InterruptConfig { receive: true, transmit: true, bus_error: true, mode_change: true, ..Default::default() },
+        ..Config::default()
+    };
+    let mut tx_fifo = FIFOConfig::<M>::tx_with_size(1, PayloadSize::Bytes8);
+    tx_fifo.interrupts.not_full_not_empty = true;
+    block_on(async {
+        driver.reset_and_apply_config(&config).await.unwrap();
+        driver.configure_fifo(tx_fifo).await.unwrap();
+        driver.configure_fifo(FIFOConfig::<RX_FIFO>::rx_with_size(4, PayloadSize::Bytes8)).await.unwrap();
+        driver.configure_filter(
+            FilterConfig::<1, RX_FIFO>::from_id(StandardId::new(0x100).unwrap()),
+            MaskConfig::<1> { match_id_type: true, id: StandardId::new(0x700).unwrap().into() },
+        ).await.unwrap();
+        driver.set_mode(OperationMode::Normal).await.unwrap();
+    });
+    driver
+}
+
+fn interrupts(simulator: &Simulator) -> Interrupts {
+    Interrupts::parse(&simulator.register(Interrupts::ADDRESS).to_le_bytes())
+}
+
+#[test]
+fn test_service_interrupt() {
+    let simulator = Simulator::new();
+    let mut driver = with_events::<TX_FIFO>(&simulator);
+    let service = |driver: &mut MCP25xxFD<Simulator>| block_on(driver.service_interrupt()).unwrap();
+
+    // Flag-only events clear their own flag and nothing else
+    assert_eq!(service(&mut driver), Some(InterruptEvent::OperationModeChange));
+    assert!(!interrupts(&simulator).modif() && interrupts(&simulator).txif());
+
+    // FIFO events stay pending until the FIFO is serviced
+    for _ in 0..2 {
+        assert_eq!(service(&mut driver), Some(InterruptEvent::Transmit { fifo: TX_FIFO }));
+    }
+    simulator.send(BusFrame::new(StandardId::new(0x123).unwrap(), &[1; 4]));
+    for _ in 0..2 {
+        assert_eq!(service(&mut driver), Some(InterruptEvent::Receive { fifo: RX_FIFO, filter_hit: 1 }));
+    }
+    assert!(interrupts(&simulator).rxif());
+    let (fifo, _) = block_on(driver.receive_from_fifo(RX_FIFO)).unwrap().unwrap();
+    assert_eq!(fifo, RX_FIFO);
+    assert_eq!(service(&mut driver), Some(InterruptEvent::Transmit { fifo: TX_FIFO }));
+
+    // The unacknowledged frame raises CERRIF, then goes through on the next attempt
+    simulator.set_acknowledge(false);
+    block_on(driver.transmit::<TX_FIFO>(&Frame::new(StandardId::new(0x7FF).unwrap(), &[2; 4]).unwrap())).unwrap();
+    simulator.set_acknowledge(true);
+    assert!(interrupts(&simulator).cerrif());
+    assert_eq!(service(&mut driver), Some(InterruptEvent::Error));
+    assert!(!interrupts(&simulator).cerrif());
+    assert_eq!(simulator.take_transmitted().len(), 1);
+    assert_eq!(service(&mut driver), Some(InterruptEvent::Transmit { fifo: TX_FIFO }));
+
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    block_on(driver.reset_and_apply_config(&Config::default())).unwrap();
+    assert_eq!(service(&mut driver), None);
+}
+
+#[test]
+fn test_service_interrupt_splits_receive_and_transmit() {
+    // CiVEC reports the highest numbered FIFO, here the TX FIFO above the RX FIFO with a frame
+    const HIGH_TX_FIFO: u8 = 3;
+    let simulator = Simulator::new();
+    let mut driver = with_events::<HIGH_TX_FIFO>(&simulator);
+    block_on(driver.service_interrupt()).unwrap();
+    simulator.send(BusFrame::new(StandardId::new(0x123).unwrap(), &[1; 4]));
+    let vector = InterruptCode::parse(&simulator.register(InterruptCode::ADDRESS).to_le_bytes());
+    assert_eq!(vector.rxcode(), InterruptFlag::FIFO2);
+    assert_eq!(block_on(driver.service_interrupt()).unwrap(), Some(InterruptEvent::Transmit { fifo: HIGH_TX_FIFO }));
+    assert_eq!(block_on(driver.receive(None)).unwrap().unwrap().0, RX_FIFO);
+}
+
+#[test]
+fn test_self_test() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let report = block_on(driver.self_test(LoopbackMode::Internal)).unwrap();
+    assert!(report.passed(), "{report:?}");
+    assert_eq!(simulator.mode(), OperationMode::Normal);
+    assert!(simulator.take_transmitted().is_empty());
+}
+
+/// SPI device failing one transaction, counting from when it is created
+struct FailingSPI {
+    simulator: Simulator,
+    transactions: usize,
+    fail_at: Option<usize>,
+    /// Transactions made while the controller was in Internal Loopback mode
+    loopback: Option<Range<usize>>,
+}
+
+impl ErrorType for FailingSPI {
+    type Error = ErrorKind;
+}
+
+impl SpiDevice for FailingSPI {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+        let transaction = self.transactions;
+        self.transactions += 1;
+        if self.fail_at == Some(transaction) {
+            return Err(ErrorKind::Other);
+        }
+        self.simulator.transaction(operations).await.unwrap();
+        if self.simulator.mode() == OperationMode::InternalLoopback {
+            let start = self.loopback.as_ref().map_or(transaction, |loopback| loopback.start);
+            self.loopback = Some(start..transaction + 1);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_self_test_restores_config_on_error() {
+    let simulator = Simulator::new();
+    NodeSetup::default().driver(&simulator);
+    let registers = [
+        FIFOControl::<TX_FIFO>::ADDRESS,
+        FIFOControl::<RX_FIFO>::ADDRESS,
+        FilterControl::<0>::ADDRESS,
+        FilterObject::<0>::ADDRESS,
+        Mask::<0>::ADDRESS,
+    ];
+    let before = registers.map(|address| simulator.register(address));
+
+    let mut spi = FailingSPI { simulator: simulator.clone(), transactions: 0, fail_at: None, loopback: None };
+    assert!(block_on(MCP25xxFD::new(&mut spi).self_test(LoopbackMode::Internal)).unwrap().passed());
+    // The last two read CiCON to leave the mode again while restoring the configuration
+    let loopback = spi.loopback.unwrap();
+
+    for fail_at in loopback.start..loopback.end - 2 {
+        let mut spi = FailingSPI { simulator: simulator.clone(), transactions: 0, fail_at: Some(fail_at), loopback: None };
+        assert!(block_on(MCP25xxFD::new(&mut spi).self_test(LoopbackMode::Internal)).is_err());
+        assert_eq!(simulator.mode(), OperationMode::Normal, "failed at {fail_at}");
+        assert_eq!(registers.map(|address| simulator.register(address)), before, "failed at {fail_at}");
+    }
+    assert!(simulator.take_transmitted().is_empty());
+}
+
+#[test]
+fn test_unacknowledged_transmission() {
+    let simulator = Simulator::new();
+    let mut tx_fifo = FIFOConfig::tx_with_size(1, PayloadSize::Bytes8);
+    tx_fifo.retransmission = RetransmissionPolicy::ThreeRetransmissions;
+    let mut driver = NodeSetup { tx_fifo, ..Default::default() }.driver(&simulator);
+    simulator.set_acknowledge(false);
+
+    let frame = Frame::new(StandardId::new(0x100).unwrap(), &[0; 8]).unwrap();
+    let outcome = block_on(async {
+        let ticket = driver.transmit_tracked::<TX_FIFO>(&frame).await.unwrap();
+        driver.wait_for_outcome(&ticket, OUTCOME_POLLS).await.unwrap()
+    });
+    assert_eq!(outcome, TransmitOutcome::Error(BusError::Acknowledge));
+    assert!(simulator.take_transmitted().is_empty());
+}
+
+#[test]
+fn test_outcome_not_requested() {
+    let simulator = Simulator::new();
+    let mut tx_fifo = FIFOConfig::tx_with_size(1, PayloadSize::Bytes8);
+    tx_fifo.retransmission = RetransmissionPolicy::ThreeRetransmissions;
+    tx_fifo.interrupts.attempts_exhausted = true;
+    let mut driver = NodeSetup { tx_fifo, ..Default::default() }.driver(&simulator);
+    simulator.set_acknowledge(false);
+
+    let frame = Frame::new(StandardId::new(0x100).unwrap(), &[0; 8]).unwrap();
+    let outcome = block_on(async {
+        let ticket = driver.transmit_tracked::<TX_FIFO>(&frame).await.unwrap();
+        driver.wait_for_outcome(&ticket, OUTCOME_POLLS).await.unwrap();
+        assert_eq!(driver.take_attempts_exhausted().await.unwrap(), 1 << TX_FIFO);
+        driver.transmit_outcome(&ticket).await.unwrap()
+    });
+    // Still queued, without a flag left to say why
+    assert_eq!(outcome, Some(TransmitOutcome::NotRequested));
+}
+
+#[test]
+fn test_monitor_fifo_fits_ram() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let too_deep = MonitorConfig { fifo_size: 32, payload_size: PayloadSize::Bytes64 };
+    assert!(block_on(driver.monitor(&too_deep)).is_err());
+
+    let mut monitor = block_on(driver.monitor(&MonitorConfig::default())).unwrap();
+    simulator.send(BusFrame::new(StandardId::new(0x123).unwrap(), &[7; 64]));
+    match block_on(monitor.receive()).unwrap() {
+        Some(MonitorEvent::Frame(frame)) => assert_eq!(frame.data(), [7; 64]),
+        event => panic!("unexpected {event:?}"),
+    }
+}
+
+#[test]
+fn test_gpio_between_transmissions() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let frame = Frame::new(StandardId::new(0x123).unwrap(), &[0; 4]).unwrap();
+    block_on(async {
+        assert!(driver.set_gpio::<1>(PinState::High).await.is_err());
+        driver.configure_gpio::<1>(PinMode::Output(PinState::Low)).await.unwrap();
+        driver.transmit::<TX_FIFO>(&frame).await.unwrap();
+        driver.set_gpio::<1>(PinState::High).await.unwrap();
+        driver.transmit::<TX_FIFO>(&frame).await.unwrap();
+        assert_eq!(driver.read_gpio_latch::<1>().await.unwrap(), PinState::High);
+    });
+    assert_eq!(simulator.take_transmitted().len(), 2);
+    // LAT1
+    assert_ne!(simulator.register(IOControl::ADDRESS) & 1 << 9, 0);
+
+    driver.gpio::<1>().unwrap().set_low().unwrap();
+    assert_eq!(simulator.register(IOControl::ADDRESS) & 1 << 9, 0);
+    assert!(driver.gpio::<0>().is_err());
+}
+
+fn io_control(simulator: &Simulator) -> IOControl {
+    IOControl::parse(&simulator.register(IOControl::ADDRESS).to_le_bytes())
+}
+
+fn with_standby(simulator: &Simulator, standby: TransceiverStandby) -> MCP25xxFD<Simulator> {
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    let config = Config { transceiver_standby: standby, ..Config::default() };
+    block_on(driver.reset_and_apply_config(&config)).unwrap();
+    driver
+}
+
+#[test]
+fn test_transceiver_standby_pin() {
+    let simulator = Simulator::new();
+    with_standby(&simulator, TransceiverStandby::Disabled);
+    let io = io_control(&simulator);
+    assert!(!io.xstbyen());
+    // GPIO0 is left an input interrupt pin after reset
+    assert!(!io.pm0() && io.tris0());
+
+    for (standby, xstbyen) in [(TransceiverStandby::Automatic, true), (TransceiverStandby::Manual, false)] {
+        let driver = with_standby(&simulator, standby);
+        let io = io_control(&simulator);
+        assert!(io.pm0() && !io.tris0() && !io.lat0(), "{standby:?}");
+        assert_eq!(io.xstbyen(), xstbyen, "{standby:?}");
+        assert!(driver.gpio_claimed(0));
+    }
+}
+
+#[test]
+fn test_manual_transceiver_standby() {
+    let simulator = Simulator::new();
+    let mut driver = with_standby(&simulator, TransceiverStandby::Manual);
+    block_on(async {
+        driver.set_mode(OperationMode::Sleep).await.unwrap();
+        assert_eq!(simulator.mode(), OperationMode::Sleep);
+        assert!(io_control(&simulator).lat0());
+        driver.set_mode(OperationMode::Configuration).await.unwrap();
+        assert!(!io_control(&simulator).lat0());
+
+        driver.set_transceiver_standby(true).await.unwrap();
+        assert!(io_control(&simulator).lat0());
+        driver.set_transceiver_standby(false).await.unwrap();
+        assert!(!io_control(&simulator).lat0());
+    });
+
+    // Only Manual lets the driver drive the pin
+    for standby in [TransceiverStandby::Disabled, TransceiverStandby::Automatic] {
+        let mut driver = with_standby(&simulator, standby);
+        block_on(async {
+            assert!(driver.set_transceiver_standby(true).await.is_err());
+            driver.set_mode(OperationMode::Sleep).await.unwrap();
+        });
+        assert!(!io_control(&simulator).lat0(), "{standby:?}");
+    }
+}
+
+#[test]
+fn test_transceiver_standby_claims_gpio0() {
+    let simulator = Simulator::new();
+    for standby in [TransceiverStandby::Automatic, TransceiverStandby::Manual] {
+        let mut driver = with_standby(&simulator, standby);
+        block_on(async {
+            assert!(driver.configure_gpio::<0>(PinMode::Output(PinState::High)).await.is_err());
+            assert!(driver.read_gpio::<0>().await.is_err());
+            // GPIO1 stays free
+            driver.configure_gpio::<1>(PinMode::Input).await.unwrap();
+        });
+        assert!(driver.gpio::<0>().is_err());
+        assert!(!io_control(&simulator).lat0());
+
+        let config = Config { transceiver_standby: standby, tx_interrupt_pin: true, ..Config::default() };
+        assert!(block_on(driver.reset_and_apply_config(&config)).is_err());
+    }
+
+    let mut driver = with_standby(&simulator, TransceiverStandby::Disabled);
+    block_on(driver.configure_gpio::<0>(PinMode::Output(PinState::High))).unwrap();
+    assert!(io_control(&simulator).lat0());
+}
+
+fn oscillator(simulator: &Simulator) -> OscillatorControl {
+    OscillatorControl::parse(&simulator.register(OscillatorControl::ADDRESS).to_le_bytes())
+}
+
+#[test]
+fn test_clock_output() {
+    let simulator = Simulator::new();
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    block_on(driver.reset_and_apply_config(&Config::default())).unwrap();
+    assert_eq!(oscillator(&simulator).clkodiv(), ClockOutputDivisor::DivideBy10);
+    assert!(!io_control(&simulator).sof());
+
+    for divisor in [
+        ClockOutputDivisor::DivideBy1,
+        ClockOutputDivisor::DivideBy2,
+        ClockOutputDivisor::DivideBy4,
+        ClockOutputDivisor::DivideBy10,
+    ] {
+        let pins = PinConfig { clock_output: ClockOutput::Clock(divisor), ..PinConfig::default() };
+        block_on(driver.reset_and_apply_config(&Config { pins, ..Config::default() })).unwrap();
+        assert_eq!(oscillator(&simulator).clkodiv(), divisor);
+        assert!(!io_control(&simulator).sof(), "{divisor:?}");
+    }
+
+    let pins = PinConfig { clock_output: ClockOutput::StartOfFrame, ..PinConfig::default() };
+    block_on(driver.reset_and_apply_config(&Config { pins, ..Config::default() })).unwrap();
+    assert!(io_control(&simulator).sof());
+    // The divisor keeps its reset value, unused while the pin outputs SOF
+    assert_eq!(oscillator(&simulator).clkodiv(), ClockOutputDivisor::DivideBy10);
+}
+
+#[test]
+fn test_open_drain_pins() {
+    let simulator = Simulator::new();
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    block_on(driver.reset_and_apply_config(&Config::default())).unwrap();
+    let io = io_control(&simulator);
+    assert!(!io.txcanod() && !io.intod());
+
+    for (txcan_open_drain, interrupt_open_drain) in [(true, false), (false, true), (true, true)] {
+        let pins = PinConfig { txcan_open_drain, interrupt_open_drain, ..PinConfig::default() };
+        block_on(driver.reset_and_apply_config(&Config { pins, ..Config::default() })).unwrap();
+        let io = io_control(&simulator);
+        assert_eq!((io.txcanod(), io.intod()), (txcan_open_drain, interrupt_open_drain));
+    }
+}
+
+#[test]
+fn test_sleep_flushes_or_aborts() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let frame = Frame::new(StandardId::new(0x123).unwrap(), &[0; 4]).unwrap();
+    let flush = SleepConfig { pending_transmissions: PendingTransmissions::Flush, ..SleepConfig::default() };
+    let abort = SleepConfig { pending_transmissions: PendingTransmissions::Abort, ..SleepConfig::default() };
+    block_on(async {
+        for _ in 0..2 {
+            driver.transmit::<TX_FIFO>(&frame).await.unwrap();
+        }
+        driver.sleep(&flush).await.unwrap();
+    });
+    assert_eq!(simulator.take_transmitted().len(), 2);
+    assert_eq!(simulator.mode(), OperationMode::Sleep);
+    assert!(block_on(driver.sleep(&flush)).is_err());
+    block_on(driver.wake_up()).unwrap();
+
+    // Without an acknowledge the frame stays pending
+    simulator.set_acknowledge(false);
+    block_on(driver.transmit::<TX_FIFO>(&frame)).unwrap();
+    assert!(block_on(driver.sleep(&flush)).is_err());
+    assert!(!driver.is_asleep());
+    assert_eq!(simulator.mode(), OperationMode::Normal);
+    block_on(driver.sleep(&abort)).unwrap();
+    assert_eq!(simulator.mode(), OperationMode::Sleep);
+    assert_eq!(simulator.register(TransmitRequest::ADDRESS), 0);
+    assert!(simulator.take_transmitted().is_empty());
+}
+
+#[test]
+fn test_wake_up_restores_config() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let can_control = simulator.register(CANControl::ADDRESS);
+    let enables = simulator.register(Interrupts::ADDRESS) >> 16;
+    let config = SleepConfig { wake_up_filter: Some(WakeUpFilterTime::T10Filter), ..SleepConfig::default() };
+
+    block_on(driver.sleep(&config)).unwrap();
+    assert!(driver.is_asleep());
+    let asleep = CANControl::parse(&simulator.register(CANControl::ADDRESS).to_le_bytes());
+    assert!(asleep.wakfil());
+    assert_eq!(asleep.wft(), WakeUpFilterTime::T10Filter);
+    assert!(Interrupts::parse(&simulator.register(Interrupts::ADDRESS).to_le_bytes()).wakeie());
+    assert!(!oscillator(&simulator).lpmen());
+
+    assert_eq!(block_on(driver.wake_up()).unwrap(), WakeUpSource::Request);
+    assert!(!driver.is_asleep());
+    assert_eq!(simulator.mode(), OperationMode::Normal);
+    assert_eq!(simulator.register(CANControl::ADDRESS), can_control);
+    assert_eq!(simulator.register(Interrupts::ADDRESS) >> 16, enables);
+    assert!(block_on(driver.wake_up()).is_err());
+}
+
+#[test]
+fn test_wake_up_on_bus() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let frame = BusFrame::new(StandardId::new(0x123).unwrap(), &[0; 4]);
+
+    let config = SleepConfig { wake_on_bus: false, ..SleepConfig::default() };
+    block_on(driver.sleep(&config)).unwrap();
+    simulator.send(frame.clone());
+    assert_eq!(simulator.mode(), OperationMode::Sleep);
+    assert_eq!(block_on(driver.wake_up()).unwrap(), WakeUpSource::Request);
+
+    block_on(driver.sleep(&SleepConfig::default())).unwrap();
+    simulator.send(frame);
+    let interrupts = Interrupts::parse(&simulator.register(Interrupts::ADDRESS).to_le_bytes());
+    assert!(interrupts.wakif());
+    assert_eq!(simulator.mode(), OperationMode::Configuration);
+    assert_eq!(block_on(driver.wake_up()).unwrap(), WakeUpSource::Bus);
+    let interrupts = Interrupts::parse(&simulator.register(Interrupts::ADDRESS).to_le_bytes());
+    assert!(!interrupts.wakif() && !interrupts.wakeie());
+    assert_eq!(simulator.mode(), OperationMode::Normal);
+    // The frame that woke the controller up is lost
+    assert!(block_on(driver.receive(None)).unwrap().is_none());
+}
+
+#[test]
+fn test_low_power_mode_resets() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    let config = SleepConfig { low_power: true, ..SleepConfig::default() };
+    block_on(driver.sleep(&config)).unwrap();
+    assert!(oscillator(&simulator).lpmen());
+
+    assert_eq!(block_on(driver.wake_up()).unwrap(), WakeUpSource::Request);
+    assert!(!driver.is_asleep());
+    assert_eq!(simulator.mode(), OperationMode::Configuration);
+    assert!(!oscillator(&simulator).lpmen());
+    // The FIFO configuration is gone until the configuration is applied again
+    assert_eq!(simulator.register(FIFOControl::<TX_FIFO>::ADDRESS), 0x0060_0000);
+}
+
+#[test]
+fn test_blocking_read() {
+    let simulator = Simulator::new();
+    let mut driver = MCP25xxFD::new(simulator);
+    let device_id: DeviceID = driver.read_register_blocking().unwrap();
+    assert_eq!(device_id.serialize(), [0x14, 0, 0, 0]);
+}
+
+/// SPI device recording the bytes written and read in each transaction
+struct RecordingSPI {
+    simulator: Simulator,
+    transactions: Vec<Vec<u8>>,
+}
+
+impl ErrorType for RecordingSPI {
+    type Error = Infallible;
+}
+
+impl SpiDevice for RecordingSPI {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        self.simulator.transaction(operations).await?;
+        let mut bytes = Vec::new();
+        for operation in operations {
+            match operation {
+                Operation::Write(data) => bytes.extend_from_slice(data),
+                Operation::Read(data) => bytes.extend_from_slice(data),
+                Operation::Transfer(read, _) => bytes.extend_from_slice(read),
+                _ => {},
+            }
+        }
+        self.transactions.push(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crc_round_trip() {
+    let simulator = Simulator::new();
+    NodeSetup::default().driver(&simulator);
+    let mut spi = RecordingSPI { simulator: simulator.clone(), transactions: Vec::new() };
+    let mut driver = MCP25xxFD::new(&mut spi);
+    let bytes = [0x12, 0x34, 0x56, 0x07];
+    block_on(async {
+        driver.write_register_safe(FilterObject::<3>::parse(&bytes)).await.unwrap();
+        let filter_object: FilterObject<3> = driver.read_register_crc().await.unwrap();
+        assert_eq!(filter_object.serialize(), bytes);
+    });
+    assert_eq!(simulator.register(FilterObject::<3>::ADDRESS), 0x0756_3412);
+
+    // CRC-16 with polynomial 0x8005 and initial value 0xFFFF over the instruction, address,
+    // length and data, worked out by hand
+    assert!(spi.transactions.contains(&vec![0xC2, 0x08, 0x12, 0x34, 0x56, 0x07, 0x6C, 0x56]));
+    assert!(spi.transactions.contains(&vec![0xB2, 0x08, 0x04, 0x12, 0x34, 0x56, 0x07, 0x3F, 0x48]));
+}
+
+#[test]
+fn test_crc16() {
+    assert_eq!(crc16(b"123456789"), 0xAEE7);
+    assert_eq!(crc16(&[]), 0xFFFF);
+}
+
+#[test]
+fn test_read_crc_mismatch() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    simulator.corrupt(1);
+    block_on(async {
+        assert!(driver.read_register_crc::<FilterObject<3>>().await.is_err());
+        driver.read_register_crc::<FilterObject<3>>().await.unwrap();
+    });
+    assert_eq!(driver.spi_crc_counters(), SPICRCCounters { read_errors: 1, ..Default::default() });
+    // Read errors are caught by the driver, the controller sees nothing wrong
+    assert_eq!(block_on(driver.check_spi_crc()).unwrap(), None);
+}
+
+#[test]
+fn test_write_safe_retries() {
+    let simulator = Simulator::new();
+    let mut driver = MCP25xxFD::new(simulator.clone());
+    let config = Config { spi_crc: SPICRCConfig { write_retries: 2, ..Default::default() }, ..Config::default() };
+    block_on(driver.reset_and_apply_config(&config)).unwrap();
+
+    simulator.corrupt(2);
+    block_on(driver.write_register_safe(FilterObject::<3>::parse(&[1, 2, 3, 4]))).unwrap();
+    assert_eq!(simulator.register(FilterObject::<3>::ADDRESS), 0x0403_0201);
+    assert_eq!(driver.spi_crc_counters(), SPICRCCounters { crc_errors: 2, write_retries: 2, ..Default::default() });
+
+    // Every attempt rejected, the register keeps its value
+    simulator.corrupt(3);
+    assert!(block_on(driver.write_register_safe(FilterObject::<3>::parse(&[5, 6, 7, 8]))).is_err());
+    assert_eq!(simulator.register(FilterObject::<3>::ADDRESS), 0x0403_0201);
+    assert_eq!(driver.spi_crc_counters(), SPICRCCounters { crc_errors: 5, write_retries: 4, ..Default::default() });
+
+    driver.clear_spi_crc_counters();
+    assert_eq!(driver.spi_crc_counters(), SPICRCCounters::default());
+}
+
+#[test]
+fn test_check_spi_crc() {
+    let simulator = Simulator::new();
+    let mut driver = NodeSetup::default().driver(&simulator);
+    assert_eq!(block_on(driver.check_spi_crc()).unwrap(), None);
+
+    // A WriteSafe one data byte short of its CRC
+    let mut spi = simulator.clone();
+    block_on(spi.write(&[0xC2, 0x08, 0x12])).unwrap();
+    let event = block_on(driver.check_spi_crc()).unwrap().unwrap();
+    assert!(event.format_error && !event.crc_error);
+
+    let mut tx = [0xC2, 0x08, 0x12, 0x34, 0x56, 0x07, 0x6C, 0x56];
+    tx[2] ^= 1;
+    block_on(spi.write(&tx)).unwrap();
+    let event = block_on(driver.check_spi_crc()).unwrap().unwrap();
+    assert!(event.crc_error && !event.format_error);
+    assert_eq!(event.crc, crc16(&tx[..6]));
+
+    assert_eq!(block_on(driver.check_spi_crc()).unwrap(), None);
+    assert_eq!(driver.spi_crc_counters(), SPICRCCounters { crc_errors: 1, format_errors: 1, ..Default::default() });
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use common::{block_on, NodeSetup, RX_FIFO, TX_FIFO};
+use common::bus::{Fault, VirtualBus};
+use common::simulator::{InterruptLine, InterruptPin, Simulator};
+use embassy_futures::join::{join, join3};
+use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embedded_can::StandardId;
+use mcp25xxfd::config::{Config, FIFOConfig, InterruptConfig};
+use mcp25xxfd::interrupt::SeparateInterruptPins;
+use mcp25xxfd::power::SleepConfig;
+use mcp25xxfd::frame::Frame;
+use mcp25xxfd::registers::*;
+use mcp25xxfd::split::SharedSPI;
+use mcp25xxfd::MCP25xxFD;
+
+const FRAMES: u16 = 4;
+
+/// A node in Normal mode with 8 deep FIFOs of 8 byte frames
+fn setup(config: Config, tx_fifo: FIFOConfig<TX_FIFO>) -> NodeSetup {
+    NodeSetup { config, tx_fifo, rx_fifo: FIFOConfig::rx_with_size(8, PayloadSize::Bytes8), ..Default::default() }
+}
+
+fn add_node(bus: &VirtualBus) -> MCP25xxFD<Simulator> {
+    setup(Config::default(), FIFOConfig::tx_with_size(8, PayloadSize::Bytes8)).driver(&bus.add_node())
+}
+
+fn frame(id: u16) -> Frame {
+    Frame::new(StandardId::new(id).unwrap(), &id.to_le_bytes().repeat(2)).unwrap()
+}
+
+#[test]
+fn test_transmit_and_receive_concurrently() {
+    let bus = VirtualBus::new();
+    let node = add_node(&bus);
+    let mut other = add_node(&bus);
+    for id in 0x10..0x10 + FRAMES {
+        block_on(other.transmit::<TX_FIFO>(&frame(id))).unwrap();
+    }
+
+    let mut shared = SharedSPI::<NoopRawMutex, _>::new();
+    let (mut tx, mut rx) = node.split(&mut shared).unwrap();
+    let transmitting = async {
+        for id in 0x20..0x20 + FRAMES {
+            tx.transmit::<TX_FIFO>(&frame(id)).await.unwrap();
+            yield_now().await;
+        }
+    };
+    let receiving = async {
+        let mut received = Vec::new();
+        while received.len() < FRAMES as usize {
+            match rx.receive(None).await.unwrap() {
+                Some((_, frame)) => received.push(frame.raw_id()),
+                None => yield_now().await,
+            }
+        }
+        received
+    };
+    let ((), received) = block_on(join(transmitting, receiving));
+    assert_eq!(received, [0x10, 0x11, 0x12, 0x13]);
+
+    let sent: Vec<_> = bus.take_log().into_iter().filter(|(node, _)| *node == 0).map(|(_, frame)| frame.id).collect();
+    let expected: Vec<_> = (0x20..0x20 + FRAMES).map(|id| frame(id).id()).collect();
+    assert_eq!(sent, expected);
+    for id in 0x20..0x20 + FRAMES {
+        let (_, frame) = block_on(other.receive(None)).unwrap().unwrap();
+        assert_eq!(frame.raw_id(), id as u32);
+    }
+}
+
+/// A node waiting on INT0 and INT1, with the receive, transmit and bus error interrupts enabled
+fn add_node_with_pins(bus: &VirtualBus, tx_fifo: FIFOConfig<TX_FIFO>) -> (MCP25xxFD<Simulator, SeparateInterruptPins<InterruptPin, InterruptPin>>, Simulator) {
+    let simulator = bus.add_node();
+    let pins = SeparateInterruptPins {
+        rx: simulator.interrupt_pin(InterruptLine::Int1),
+        tx: simulator.interrupt_pin(InterruptLine::Int0),
+    };
+    let config = Config {
+        tx_interrupt_pin: true,
+        rx_interrupt_pin: true,
+        interrupts: InterruptConfig { receive: true, transmit: true, bus_error: true, ..Default::default() },
+        ..Config::default()
+    };
+    let mut driver = MCP25xxFD::with_interrupt_pins(simulator.clone(), pins);
+    setup(config, tx_fifo).apply(&mut driver);
+    (driver, simulator)
+}
+
+fn interrupt_enables(simulator: &Simulator) -> u32 {
+    simulator.register(Interrupts::ADDRESS) >> 16
+}
+
+#[test]
+fn test_halves_wait_on_separate_pins() {
+    let bus = VirtualBus::new();
+    // The not full interrupt asserts INT0 once there is room again
+    let mut tx_fifo = FIFOConfig::tx_with_size(2, PayloadSize::Bytes8);
+    tx_fifo.interrupts.not_full_not_empty = true;
+    let (mut node, simulator) = add_node_with_pins(&bus, tx_fifo);
+    let mut other = add_node(&bus);
+    bus.hold(true);
+    for id in 0x20..0x22 {
+        block_on(node.transmit::<TX_FIFO>(&frame(id))).unwrap();
+    }
+    let enables = interrupt_enables(&simulator);
+
+    let mut shared = SharedSPI::<NoopRawMutex, _>::new();
+    let (mut tx, mut rx) = node.split(&mut shared).unwrap();
+    // Waits for room on INT0 and for a frame on INT1 at the same time
+    let next = frame(0x22);
+    let transmitting = tx.transmit::<TX_FIFO>(&next);
+    let receiving = rx.receive(None);
+    let driving = async {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+        // Neither half touches the enables while waiting
+        assert_eq!(interrupt_enables(&simulator), enables);
+        other.transmit::<TX_FIFO>(&frame(0x10)).await.unwrap();
+        bus.hold(false);
+        for _ in 0..4 {
+            bus.step();
+            yield_now().await;
+        }
+    };
+    let (sent, received, ()) = block_on(join3(transmitting, receiving, driving));
+    sent.unwrap();
+    assert_eq!(received.unwrap().unwrap().1.raw_id(), 0x10);
+    assert_eq!(interrupt_enables(&simulator), enables);
+    let sent: Vec<_> = bus.take_log().into_iter().filter(|(node, _)| *node == 0).map(|(_, frame)| frame.id).collect();
+    let expected: Vec<_> = (0x20..0x23).map(|id| frame(id).id()).collect();
+    assert_eq!(sent, expected);
+}
+
+#[test]
+fn test_bus_error_leaves_transmit_interrupts() {
+    let bus = VirtualBus::new();
+    // The not full TX FIFO keeps TXIF set
+    let mut tx_fifo = FIFOConfig::tx_with_size(2, PayloadSize::Bytes8);
+    tx_fifo.interrupts.not_full_not_empty = true;
+    let (node, simulator) = add_node_with_pins(&bus, tx_fifo);
+    let mut other = add_node(&bus);
+    bus.inject(Fault::ErrorFrame, 1);
+    block_on(other.transmit::<TX_FIFO>(&frame(0x10))).unwrap();
+    let before = Interrupts::parse(&simulator.register(Interrupts::ADDRESS).to_le_bytes());
+    assert!(before.cerrif() && before.txif() && before.txie());
+
+    let mut shared = SharedSPI::<NoopRawMutex, _>::new();
+    let (_tx, mut rx) = node.split(&mut shared).unwrap();
+    assert!(block_on(rx.receive(None)).is_err());
+    let after = Interrupts::parse(&simulator.register(Interrupts::ADDRESS).to_le_bytes());
+    assert!(!after.cerrif() && after.txif());
+    assert_eq!(after.serialize()[2..], before.serialize()[2..]);
+}
+
+#[test]
+fn test_halves_keep_driver_state() {
+    let bus = VirtualBus::new();
+    let mut node = add_node(&bus);
+    let mut other = add_node(&bus);
+    // One frame more than the RX FIFO holds
+    for id in 0x10..0x19 {
+        block_on(other.transmit::<TX_FIFO>(&frame(id))).unwrap();
+    }
+    block_on(node.receive(None)).unwrap().unwrap();
+    assert_eq!(node.receive_overflows().count(RX_FIFO), 1);
+    let first = block_on(node.transmit_tracked::<TX_FIFO>(&frame(0x20))).unwrap();
+
+    let mut shared = SharedSPI::<NoopRawMutex, _>::new();
+    let (mut tx, mut rx) = node.split(&mut shared).unwrap();
+    assert_eq!(rx.take_receive_overflow(), Some(RX_FIFO));
+    let second = block_on(tx.transmit_tracked::<TX_FIFO>(&frame(0x21))).unwrap();
+    assert_eq!(second.sequence_number, first.sequence_number + 1);
+}
+
+#[test]
+fn test_split_while_asleep() {
+    let bus = VirtualBus::new();
+    let mut node = add_node(&bus);
+    block_on(node.sleep(&SleepConfig::default())).unwrap();
+    let mut shared = SharedSPI::<NoopRawMutex, _>::new();
+    assert!(node.split(&mut shared).is_err());
+}
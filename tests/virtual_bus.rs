@@ -0,0 +1,275 @@
+mod common;
+
+use common::{block_on, NodeSetup, RX_FIFO, TX_FIFO};
+use common::bus::{Fault, VirtualBus};
+use common::simulator::{BusFrame, Simulator};
+use embedded_can::{ExtendedId, StandardId};
+use mcp25xxfd::autobaud::AutoBaudConfig;
+use mcp25xxfd::config::{ArbitrationBitRate, BitRate, BitTiming, Clock, Config, DataBitRate, FIFOConfig, RetransmissionPolicy};
+use mcp25xxfd::frame::Frame;
+use mcp25xxfd::registers::*;
+use mcp25xxfd::transmit::{BusError, TransmitOutcome};
+use mcp25xxfd::MCP25xxFD;
+
+const OUTCOME_POLLS: usize = 10_000;
+
+/// A node in Normal mode with an 8 deep TX FIFO and an RX FIFO taking every frame
+fn add_node(bus: &VirtualBus, config: &Config, retransmission: RetransmissionPolicy) -> MCP25xxFD<Simulator> {
+    let mut tx_fifo = FIFOConfig::tx_with_size(8, PayloadSize::Bytes64);
+    tx_fifo.retransmission = retransmission;
+    let rx_fifo = FIFOConfig::rx_with_size(8, PayloadSize::Bytes64);
+    NodeSetup { config: config.clone(), tx_fifo, rx_fifo, ..Default::default() }.driver(&bus.add_node())
+}
+
+fn frame(id: u16, data: &[u8]) -> Frame {
+    Frame::new(StandardId::new(id).unwrap(), data).unwrap()
+}
+
+#[test]
+fn test_frames_reach_other_nodes() {
+    let bus = VirtualBus::new();
+    let mut nodes: Vec<_> = (0..3).map(|_| add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited)).collect();
+    block_on(nodes[1].transmit::<TX_FIFO>(&frame(0x321, &[1, 2, 3, 4]))).unwrap();
+
+    assert_eq!(bus.take_log(), [(1, BusFrame::new(StandardId::new(0x321).unwrap(), &[1, 2, 3, 4]))]);
+    for node in [0, 2] {
+        let (fifo, received) = block_on(nodes[node].receive(None)).unwrap().unwrap();
+        assert_eq!(fifo, RX_FIFO);
+        assert_eq!(received.raw_id(), 0x321);
+        assert_eq!(received.data(), [1, 2, 3, 4]);
+    }
+    assert!(block_on(nodes[1].receive(None)).unwrap().is_none());
+}
+
+#[test]
+fn test_arbitration() {
+    let bus = VirtualBus::new();
+    let mut nodes: Vec<_> = (0..3).map(|_| add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited)).collect();
+    // Same base identifier as the standard frame, which wins since IDE is recessive
+    let extended = ExtendedId::new(0x200 << 18).unwrap();
+    bus.hold(true);
+    block_on(async {
+        nodes[0].transmit::<TX_FIFO>(&Frame::new(extended, &[0; 4]).unwrap()).await.unwrap();
+        nodes[1].transmit::<TX_FIFO>(&frame(0x200, &[1; 4])).await.unwrap();
+        nodes[2].transmit::<TX_FIFO>(&frame(0x100, &[2; 4])).await.unwrap();
+    });
+    for _ in 0..3 {
+        bus.step();
+    }
+
+    let senders: Vec<_> = bus.take_log().into_iter().map(|(node, _)| node).collect();
+    assert_eq!(senders, [2, 1, 0]);
+}
+
+#[test]
+fn test_attempts_lost_in_arbitration() {
+    let bus = VirtualBus::new();
+    let mut sender = add_node(&bus, &Config::default(), RetransmissionPolicy::ThreeRetransmissions);
+    let mut busy = add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    bus.hold(true);
+    let ticket = block_on(async {
+        for _ in 0..5 {
+            busy.transmit::<TX_FIFO>(&frame(0x100, &[0; 8])).await.unwrap();
+        }
+        sender.transmit_tracked::<TX_FIFO>(&frame(0x300, &[0; 8])).await.unwrap()
+    });
+    bus.hold(false);
+
+    let outcome = block_on(sender.wait_for_outcome(&ticket, OUTCOME_POLLS)).unwrap();
+    assert_eq!(outcome, TransmitOutcome::AttemptsExhausted);
+    assert!(bus.take_log().iter().all(|(node, _)| *node == 1));
+}
+
+#[test]
+fn test_bit_rate_mismatch() {
+    let bus = VirtualBus::new();
+    let mut sender = add_node(&bus, &Config::default(), RetransmissionPolicy::ThreeRetransmissions);
+    let slow = Config {
+        bit_timing: BitRate { arbitration: ArbitrationBitRate::Rate250K, data: DataBitRate::Rate2M }.into(),
+        ..Config::default()
+    };
+    let mut receiver = add_node(&bus, &slow, RetransmissionPolicy::Unlimited);
+
+    let outcome = block_on(async {
+        let ticket = sender.transmit_tracked::<TX_FIFO>(&frame(0x123, &[0; 8])).await.unwrap();
+        sender.wait_for_outcome(&ticket, OUTCOME_POLLS).await.unwrap()
+    });
+    assert_eq!(outcome, TransmitOutcome::Error(BusError::Form));
+    let errors: TransmitReceiveErrorCount = block_on(receiver.read_register()).unwrap();
+    assert_eq!(errors.rec(), 4);
+    assert!(bus.take_log().is_empty());
+}
+
+#[test]
+fn test_missing_acknowledge() {
+    let bus = VirtualBus::new();
+    let mut sender = add_node(&bus, &Config::default(), RetransmissionPolicy::ThreeRetransmissions);
+    let mut receiver = add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    bus.inject(Fault::MissingAcknowledge, 4);
+
+    let outcome = block_on(async {
+        let ticket = sender.transmit_tracked::<TX_FIFO>(&frame(0x123, &[0; 8])).await.unwrap();
+        sender.wait_for_outcome(&ticket, OUTCOME_POLLS).await.unwrap()
+    });
+    assert_eq!(outcome, TransmitOutcome::Error(BusError::Acknowledge));
+    assert!(block_on(receiver.receive(None)).unwrap().is_none());
+
+    // The faults are used up. The frame that ran out of attempts is still first in the FIFO, so
+    // it goes out again when the next one is requested.
+    block_on(async {
+        sender.transmit::<TX_FIFO>(&frame(0x124, &[0; 8])).await.unwrap();
+        for id in [0x123, 0x124] {
+            let (_, received) = receiver.receive(None).await.unwrap().unwrap();
+            assert_eq!(received.raw_id(), id);
+        }
+    });
+}
+
+#[test]
+fn test_bus_off_recovery() {
+    let bus = VirtualBus::new();
+    let mut sender = add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    let mut receiver = add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    // Each error adds 8 to the transmit error counter, the 32nd one takes it past 255
+    bus.inject(Fault::ErrorFrame, 32);
+    let bus_off = block_on(async {
+        sender.transmit::<TX_FIFO>(&frame(0x123, &[5; 8])).await.unwrap();
+        for _ in 0..100 {
+            let errors: TransmitReceiveErrorCount = sender.read_register().await.unwrap();
+            if errors.txbo() {
+                return true;
+            }
+        }
+        false
+    });
+    assert!(bus_off);
+    let diagnostic: BusDiagnostic1 = block_on(sender.read_register()).unwrap();
+    assert_eq!(BusError::from(diagnostic), BusError::BusOff);
+    assert!(bus.take_log().is_empty());
+
+    // The frame goes out once the sender recovered
+    let received = block_on(async {
+        for _ in 0..1000 {
+            // The error frames are reported as a bus error first
+            if let Ok(Some((_, received))) = receiver.receive(None).await {
+                return Some(received);
+            }
+        }
+        None
+    });
+    assert_eq!(received.unwrap().data(), [5; 8]);
+    let errors: TransmitReceiveErrorCount = block_on(sender.read_register()).unwrap();
+    assert!(!errors.txbo());
+}
+
+#[test]
+fn test_forced_bus_off() {
+    let bus = VirtualBus::new();
+    let mut sender = add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    bus.force_bus_off(0);
+    block_on(sender.transmit::<TX_FIFO>(&frame(0x123, &[0; 8]))).unwrap();
+    assert!(bus.take_log().is_empty());
+
+    while bus.take_log().is_empty() {
+        bus.step();
+    }
+    let errors: TransmitReceiveErrorCount = block_on(sender.read_register()).unwrap();
+    assert_eq!(errors.tec(), 0);
+}
+
+#[test]
+fn test_abort_outcome() {
+    let bus = VirtualBus::new();
+    let mut sender = add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    block_on(async {
+        let sent = sender.transmit_tracked::<TX_FIFO>(&frame(0x100, &[0; 8])).await.unwrap();
+        bus.hold(true);
+        let aborted = sender.transmit_tracked::<TX_FIFO>(&frame(0x101, &[1; 8])).await.unwrap();
+        assert_eq!(sender.abort_fifo(TX_FIFO).await.unwrap().count, 1);
+        // Queued in the object the aborted frame had
+        bus.hold(false);
+        let after = sender.transmit_tracked::<TX_FIFO>(&frame(0x102, &[2; 8])).await.unwrap();
+
+        assert!(matches!(sender.transmit_outcome(&sent).await.unwrap(), Some(TransmitOutcome::Sent { .. })));
+        assert_eq!(sender.transmit_outcome(&aborted).await.unwrap(), Some(TransmitOutcome::Aborted));
+        assert!(matches!(sender.wait_for_outcome(&after, OUTCOME_POLLS).await.unwrap(), TransmitOutcome::Sent { .. }));
+    });
+    let sent: Vec<_> = bus.take_log().into_iter().map(|(_, frame)| frame.id).collect();
+    assert_eq!(sent, [frame(0x100, &[]).id(), frame(0x102, &[]).id()]);
+}
+
+#[test]
+fn test_tracked_transmit_queue() {
+    let bus = VirtualBus::new();
+    let config = Config { txq_enabled: true, ..Config::default() };
+    let mut sender = add_node(&bus, &config, RetransmissionPolicy::Unlimited);
+    add_node(&bus, &Config::default(), RetransmissionPolicy::Unlimited);
+    block_on(async {
+        bus.hold(true);
+        let aborted = sender.transmit_tracked::<0>(&frame(0x100, &[0; 8])).await.unwrap();
+        assert_eq!(aborted.fifo, 0);
+        sender.abort_transmit_queue().await.unwrap();
+        assert_eq!(sender.transmit_outcome(&aborted).await.unwrap(), Some(TransmitOutcome::Aborted));
+
+        bus.hold(false);
+        let sent = sender.transmit_tracked::<0>(&frame(0x101, &[1; 8])).await.unwrap();
+        assert!(matches!(sender.wait_for_outcome(&sent, OUTCOME_POLLS).await.unwrap(), TransmitOutcome::Sent { .. }));
+    });
+    assert_eq!(bus.take_log().len(), 1);
+}
+
+/// Two nodes at 250 kbit/s keeping the bus busy with 64 frames, and a third node to listen
+fn talking_bus() -> (VirtualBus, MCP25xxFD<Simulator>) {
+    let bus = VirtualBus::new();
+    let config = Config {
+        bit_timing: BitRate { arbitration: ArbitrationBitRate::Rate250K, data: DataBitRate::Rate1M }.into(),
+        ..Config::default()
+    };
+    bus.hold(true);
+    for node in 0..2 {
+        let mut talker = MCP25xxFD::new(bus.add_node());
+        block_on(async {
+            talker.reset_and_apply_config(&config).await.unwrap();
+            talker.configure_fifo(FIFOConfig::<TX_FIFO>::tx_with_size(32, PayloadSize::Bytes8)).await.unwrap();
+            talker.set_mode(OperationMode::Normal).await.unwrap();
+            for id in 0..32 {
+                talker.transmit::<TX_FIFO>(&frame(0x100 * node + id, &[0; 8])).await.unwrap();
+            }
+        });
+    }
+    let mut listener = MCP25xxFD::new(bus.add_node());
+    block_on(listener.reset_and_apply_config(&Config::default())).unwrap();
+    bus.hold(false);
+    (bus, listener)
+}
+
+fn candidate(arbitration: ArbitrationBitRate) -> BitTiming {
+    BitRate { arbitration, data: DataBitRate::Rate1M }.into()
+}
+
+#[test]
+fn test_detect_bit_rate() {
+    let (_bus, mut listener) = talking_bus();
+    let config = AutoBaudConfig { polls_per_candidate: 8, ..AutoBaudConfig::default() };
+    let candidates = [ArbitrationBitRate::Rate500K, ArbitrationBitRate::Rate125K, ArbitrationBitRate::Rate250K].map(candidate);
+    let result = block_on(listener.detect_bit_rate(&Clock::Clock40MHz, candidates, &config)).unwrap().unwrap();
+
+    let expected = BitRate { arbitration: ArbitrationBitRate::Rate250K, data: DataBitRate::Rate1M };
+    assert_eq!(result.config, expected.get_config(&Clock::Clock40MHz).unwrap());
+    assert!(result.frames >= config.min_frames);
+    assert_eq!(result.errors, 0);
+    // Left in Configuration mode to apply the result
+    assert_eq!(block_on(listener.read_register::<CANControl>()).unwrap().opmode(), OperationMode::Configuration);
+}
+
+#[test]
+fn test_detect_bit_rate_without_match() {
+    let (bus, mut listener) = talking_bus();
+    let config = AutoBaudConfig { polls_per_candidate: 8, ..AutoBaudConfig::default() };
+    let candidates = [ArbitrationBitRate::Rate500K, ArbitrationBitRate::Rate1000K].map(candidate);
+    assert!(block_on(listener.detect_bit_rate(&Clock::Clock40MHz, candidates, &config)).unwrap().is_none());
+    // Frames kept going through between the talkers
+    assert!(!bus.take_log().is_empty());
+}